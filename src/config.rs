@@ -0,0 +1,424 @@
+//! Reader for the TOML subset used by the ptltectl config file: `[table]`
+//! headers (dotted for nesting), `key = value` lines, and string, integer,
+//! boolean and single-line array values. `#` comments and blank lines are
+//! ignored. Inline tables, multi-line values and dotted keys are not
+//! supported and are reported as errors rather than misread.
+//!
+//! Several files can be layered: later ones override earlier ones, tables
+//! merging key by key at every depth and any other value replacing outright.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+pub type Table = BTreeMap<String, Value>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Config {
+    pub root: Table,
+    /// The files that were read, lowest precedence first.
+    pub paths: Vec<PathBuf>,
+}
+
+impl Config {
+    /// Layers `paths` in order; when none are given, the colon-separated
+    /// `$PTLTECTL_CONFIG_PATH`, or else the default location. A missing
+    /// default file yields an empty config; a missing named one is an error.
+    pub fn load(paths: &[PathBuf]) -> Result<Config, String> {
+        let (paths, explicit) = if !paths.is_empty() {
+            (paths.to_vec(), true)
+        } else if let Some(list) = env::var_os("PTLTECTL_CONFIG_PATH").filter(|l| !l.is_empty()) {
+            (env::split_paths(&list).collect(), true)
+        } else {
+            (default_path().into_iter().collect(), false)
+        };
+        Config::read(&paths, explicit)
+    }
+
+    /// Layers `paths` in order. Unless they were `explicit`ly named, ones
+    /// that do not exist are skipped.
+    fn read(paths: &[PathBuf], explicit: bool) -> Result<Config, String> {
+        let mut config = Config::default();
+        for path in paths {
+            let text = match fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(err) if !explicit && err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(format!("{}: {err}", path.display())),
+            };
+            let layer = parse(&text).map_err(|err| format!("{}: {err}", path.display()))?;
+            merge(&mut config.root, layer);
+            config.paths.push(path.clone());
+        }
+        Ok(config)
+    }
+
+    /// Looks up a top-level boolean, treating an absent key as `false`.
+    pub fn flag(&self, key: &str) -> Result<bool, String> {
+        match self.root.get(key) {
+            None => Ok(false),
+            Some(Value::Boolean(value)) => Ok(*value),
+            Some(other) => Err(self.type_error(key, "boolean", other)),
+        }
+    }
+
+    /// Entries of a top-level table, empty when the table is absent.
+    pub fn table(&self, key: &str) -> Result<impl Iterator<Item = (&String, &Value)>, String> {
+        let entries = match self.root.get(key) {
+            None => None,
+            Some(Value::Table(table)) => Some(table.iter()),
+            Some(other) => return Err(self.type_error(key, "table", other)),
+        };
+        Ok(entries.into_iter().flatten())
+    }
+
+    /// Unwraps a string entry found under `[table]`.
+    pub fn string_in<'a>(
+        &self,
+        table: &str,
+        key: &str,
+        value: &'a Value,
+    ) -> Result<&'a str, String> {
+        match value {
+            Value::String(value) => Ok(value),
+            other => Err(self.type_error(&format!("{table}.{key}"), "string", other)),
+        }
+    }
+
+    /// Unwraps an integer entry found under `[table]`.
+    pub fn integer_in(&self, table: &str, key: &str, value: &Value) -> Result<i64, String> {
+        match value {
+            Value::Integer(value) => Ok(*value),
+            other => Err(self.type_error(&format!("{table}.{key}"), "integer", other)),
+        }
+    }
+
+    /// Unwraps a nested table found under `[table]`.
+    pub fn table_in<'a>(
+        &self,
+        table: &str,
+        key: &str,
+        value: &'a Value,
+    ) -> Result<&'a Table, String> {
+        match value {
+            Value::Table(inner) => Ok(inner),
+            other => Err(self.type_error(&format!("{table}.{key}"), "table", other)),
+        }
+    }
+
+    fn type_error(&self, key: &str, expected: &str, found: &Value) -> String {
+        let origin = match self.paths.as_slice() {
+            [] => String::new(),
+            paths => {
+                let names: Vec<_> = paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                format!("{}: ", names.join(", "))
+            }
+        };
+        format!(
+            "{origin}`{key}` must be a {expected}, found {}",
+            found.type_name()
+        )
+    }
+}
+
+/// Lays `layer` over `base`: tables present in both merge recursively, and
+/// everything else in `layer` wins.
+fn merge(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(inner)) => merge(existing, inner),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/ptltectl/config.toml`, falling back to `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("ptltectl").join("config.toml"))
+}
+
+pub fn parse(text: &str) -> Result<Table, String> {
+    let mut root = Table::new();
+    let mut current: Vec<String> = Vec::new();
+
+    for (index, raw) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let result = if let Some(header) = line.strip_prefix('[') {
+            parse_header(header).and_then(|path| {
+                table_at(&mut root, &path)?;
+                current = path;
+                Ok(())
+            })
+        } else {
+            parse_pair(line).and_then(|(key, value)| {
+                let table = table_at(&mut root, &current)?;
+                if table.contains_key(&key) {
+                    return Err(format!("duplicate key `{key}`"));
+                }
+                table.insert(key, value);
+                Ok(())
+            })
+        };
+        result.map_err(|err| format!("line {line_no}: {err}"))?;
+    }
+
+    Ok(root)
+}
+
+fn table_at<'a>(root: &'a mut Table, path: &[String]) -> Result<&'a mut Table, String> {
+    let mut table = root;
+    for segment in path {
+        let entry = table
+            .entry(segment.clone())
+            .or_insert_with(|| Value::Table(Table::new()));
+        table = match entry {
+            Value::Table(inner) => inner,
+            other => {
+                return Err(format!(
+                    "`{segment}` is a {}, not a table",
+                    other.type_name()
+                ));
+            }
+        };
+    }
+    Ok(table)
+}
+
+fn parse_header(rest: &str) -> Result<Vec<String>, String> {
+    let (inner, trailing) = rest
+        .split_once(']')
+        .ok_or_else(|| "unterminated table header".to_string())?;
+    expect_end(trailing)?;
+    inner
+        .split('.')
+        .map(|segment| parse_key(segment.trim()))
+        .collect()
+}
+
+fn parse_pair(line: &str) -> Result<(String, Value), String> {
+    let (key, rest) = line
+        .split_once('=')
+        .ok_or_else(|| "expected `key = value`".to_string())?;
+    let key = key.trim();
+    if key.contains('.') && !key.starts_with('"') {
+        return Err(format!(
+            "dotted key `{key}` is not supported; use a [table]"
+        ));
+    }
+    let key = parse_key(key)?;
+    let (value, trailing) = parse_value(rest.trim_start())?;
+    expect_end(trailing)?;
+    Ok((key, value))
+}
+
+fn parse_key(key: &str) -> Result<String, String> {
+    if let Some(quoted) = key.strip_prefix('"') {
+        let (key, trailing) = parse_basic_string(quoted)?;
+        expect_end(trailing)?;
+        return Ok(key);
+    }
+    let bare = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if key.is_empty() || !key.chars().all(bare) {
+        return Err(format!("invalid key `{key}`"));
+    }
+    Ok(key.to_string())
+}
+
+fn parse_value(text: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = text.strip_prefix('"') {
+        let (value, rest) = parse_basic_string(rest)?;
+        return Ok((Value::String(value), rest));
+    }
+    if let Some(rest) = text.strip_prefix('\'') {
+        let (value, rest) = rest
+            .split_once('\'')
+            .ok_or_else(|| "unterminated string".to_string())?;
+        return Ok((Value::String(value.to_string()), rest));
+    }
+    if let Some(rest) = text.strip_prefix('[') {
+        return parse_array(rest);
+    }
+
+    let end = text
+        .find(|c: char| c == ',' || c == ']' || c == '#' || c.is_whitespace())
+        .unwrap_or(text.len());
+    let (token, rest) = text.split_at(end);
+    let value = match token {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        "" => return Err("missing value".into()),
+        _ => Value::Integer(parse_integer(token)?),
+    };
+    Ok((value, rest))
+}
+
+fn parse_array(mut text: &str) -> Result<(Value, &str), String> {
+    let mut items = Vec::new();
+    loop {
+        text = text.trim_start();
+        if let Some(rest) = text.strip_prefix(']') {
+            return Ok((Value::Array(items), rest));
+        }
+        if text.is_empty() {
+            return Err("unterminated array".into());
+        }
+        let (item, rest) = parse_value(text)?;
+        items.push(item);
+        text = rest.trim_start();
+        if let Some(rest) = text.strip_prefix(',') {
+            text = rest;
+        } else if !text.starts_with(']') {
+            return Err("expected `,` or `]` in array".into());
+        }
+    }
+}
+
+fn parse_basic_string(text: &str) -> Result<(String, &str), String> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &text[index + 1..])),
+            '\\' => match chars.next().map(|(_, escaped)| escaped) {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(other) => return Err(format!("unsupported escape `\\{other}`")),
+                None => break,
+            },
+            _ => value.push(c),
+        }
+    }
+    Err("unterminated string".into())
+}
+
+fn parse_integer(token: &str) -> Result<i64, String> {
+    let cleaned = token.replace('_', "");
+    let parsed = match cleaned.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => cleaned.parse(),
+    };
+    parsed.map_err(|_| format!("invalid value `{token}`"))
+}
+
+fn expect_end(text: &str) -> Result<(), String> {
+    let text = text.trim_start();
+    if text.is_empty() || text.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected `{text}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `files` to a fresh directory and returns their paths in order.
+    fn write_files(name: &str, files: &[&str]) -> Vec<PathBuf> {
+        let dir = env::temp_dir().join(format!("ptltectl-config-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        files
+            .iter()
+            .enumerate()
+            .map(|(index, text)| {
+                let path = dir.join(format!("{index}.toml"));
+                fs::write(&path, text).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nested_tables_merge_key_by_key() {
+        let paths = write_files(
+            "nested",
+            &[
+                "[scenes.build]\nred = \"on\"\ngreen = \"off\"\n[cvd]\nred = \"pattern1\"\n",
+                "[scenes.build]\ngreen = \"on\"\n[scenes.idle]\nblue = \"on\"\n",
+            ],
+        );
+        let config = Config::read(&paths, true).unwrap();
+        let expected = parse(
+            "[scenes.build]\nred = \"on\"\ngreen = \"on\"\n[scenes.idle]\nblue = \"on\"\n[cvd]\nred = \"pattern1\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.root, expected);
+        assert_eq!(config.paths, paths);
+    }
+
+    #[test]
+    fn scalars_and_arrays_replace_outright() {
+        let paths = write_files(
+            "replace",
+            &[
+                "strict = false\nlist = [1, 2, 3]\nname = \"a\"\n",
+                "strict = true\nlist = [4]\n",
+            ],
+        );
+        let config = Config::read(&paths, true).unwrap();
+        assert_eq!(config.root["strict"], Value::Boolean(true));
+        assert_eq!(config.root["list"], Value::Array(vec![Value::Integer(4)]));
+        assert_eq!(config.root["name"], Value::String("a".into()));
+    }
+
+    #[test]
+    fn table_replaces_scalar_and_scalar_replaces_table() {
+        let mut base = parse("a = 1\n[b]\nc = 2\n").unwrap();
+        merge(&mut base, parse("b = 3\n[a]\nc = 4\n").unwrap());
+        assert_eq!(base, parse("b = 3\n[a]\nc = 4\n").unwrap());
+    }
+
+    #[test]
+    fn missing_explicit_file_is_an_error() {
+        let missing = env::temp_dir().join("ptltectl-config-missing-explicit.toml");
+        let err = Config::read(std::slice::from_ref(&missing), true)
+            .err()
+            .expect("a named file that does not exist must fail");
+        assert!(err.starts_with(&missing.display().to_string()), "{err}");
+    }
+
+    #[test]
+    fn missing_default_file_is_skipped() {
+        let missing = env::temp_dir().join("ptltectl-config-missing-default.toml");
+        let config = Config::read(&[missing], false).unwrap();
+        assert!(config.root.is_empty());
+        assert!(config.paths.is_empty());
+    }
+}
@@ -0,0 +1,1368 @@
+//! Report building and delivery for Patlite LR6-USB signal towers.
+//!
+//! A report is `REPORT_LEN` bytes: the buzzer and pitch nibbles followed by
+//! one nibble per lamp. `build_report` lays them out, `assemble_leds` packs
+//! lamp states, the parsers accept the same aliases as the `ptltectl` command
+//! line, and `send_report` writes the result to the first attached tower.
+//! Nothing here prints or exits; every failure comes back as a `ControlError`.
+//!
+//! ```no_run
+//! use ptltectl::{BUZZER_KEEP, COLOR_RED, LED_ON, assemble_leds, build_report, send_report};
+//!
+//! let (led_ry, led_gb, led_w) = assemble_leds(&[COLOR_RED], LED_ON)?;
+//! send_report(&build_report(BUZZER_KEEP, 0, led_ry, led_gb, led_w))?;
+//! # Ok::<(), ptltectl::ControlError>(())
+//! ```
+
+use retry::{OperationResult, delay::Fixed, retry};
+use rusb::UsbContext;
+use std::fmt;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const VENDOR_ID: u16 = 0x191a;
+pub const PRODUCT_ID: u16 = 0x8003;
+// Patlite models sharing the LR6-USB report format; the first is preferred
+// when several are attached.
+pub const SUPPORTED_PRODUCTS: &[u16] = &[PRODUCT_ID, 0x6001];
+pub const ENDPOINT_OUT: u8 = 0x01;
+pub const ENDPOINT_IN: u8 = 0x81;
+pub const TIMEOUT_MS: u64 = 1000;
+pub const REPORT_LEN: usize = 8;
+// Allow concurrent commands ~1s for the kernel to release the HID interface.
+pub const BUSY_RETRY_ATTEMPTS: usize = 20;
+pub const BUSY_RETRY_DELAY_MS: u64 = 50;
+// How long a tower that re-enumerated after a USB reset gets to come back.
+const USB_RESET_SETTLE_MS: u64 = 500;
+
+// Exit status when the tower disappears between open and write.
+pub const EXIT_DISCONNECTED: i32 = 3;
+
+pub const COMMAND_VERSION: u8 = 0x00;
+pub const COMMAND_ID: u8 = 0x00;
+
+pub const COLOR_RED: u8 = 0;
+pub const COLOR_YELLOW: u8 = 1;
+pub const COLOR_GREEN: u8 = 2;
+pub const COLOR_BLUE: u8 = 3;
+pub const COLOR_WHITE: u8 = 4;
+pub const COLOR_COUNT: usize = 5;
+pub const COLOR_NAMES: [&str; COLOR_COUNT] = ["red", "yellow", "green", "blue", "white"];
+
+pub const LED_KEEP: u8 = 0x0F;
+pub const LED_OFF: u8 = 0x00;
+pub const LED_ON: u8 = 0x01;
+pub const LED_KEEP_PAIR: u8 = (LED_KEEP << 4) | LED_KEEP;
+pub const LED_KEEP_HIGH: u8 = LED_KEEP << 4;
+
+pub const BUZZER_KEEP: u8 = 0x0F;
+pub const BUZZER_OFF: u8 = 0x00;
+pub const BUZZER_ON: u8 = 0x01;
+pub const BUZZER_PITCH_DEFAULT_A: u8 = 0x0E;
+pub const BUZZER_PITCH_DEFAULT_B: u8 = 0x0F;
+pub const PITCH_OFF: u8 = 0x00;
+
+pub type ControlResult<T> = Result<T, ControlError>;
+
+/// Everything that can go wrong finding, opening or writing to a tower, or
+/// with the input that describes what to write.
+#[derive(Debug)]
+pub enum ControlError {
+    /// Vendor id and the product ids that were looked for.
+    DeviceNotFound(u16, Vec<u16>),
+    DeviceOpenFailed(rusb::Error),
+    SerialNotFound(String),
+    LocationNotFound(u8, u8),
+    AmbiguousDevice(usize),
+    DeviceDisconnected,
+    InvalidArg(String),
+    Usb {
+        op: &'static str,
+        source: rusb::Error,
+    },
+    /// Bytes the tower acknowledged and the bytes that were written.
+    ShortWrite {
+        written: usize,
+        expected: usize,
+    },
+    ShortRead,
+    EndpointMissing(Vec<u8>),
+    FirmwareTooOld {
+        found: Option<rusb::Version>,
+        required: rusb::Version,
+    },
+    ReadUnavailable(rusb::Error),
+    ReadTimeout(Duration),
+    InvalidReport([u8; REPORT_LEN], Vec<String>),
+    ReportFile(PathBuf, std::io::Error),
+    File(PathBuf, std::io::Error),
+    Config(String),
+    ScriptLine(usize, Box<ControlError>),
+    ScriptFailed(Vec<ControlError>),
+    ProbeUnsafe {
+        report: [u8; REPORT_LEN],
+        cause: Box<ControlError>,
+        reset: Option<Box<ControlError>>,
+    },
+    Interrupted,
+    HeldBy(u32, String),
+    /// Pid of the server a command was sent through, and its error reply.
+    Daemon(u32, String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlError::DeviceNotFound(vendor, products) => {
+                let ids: Vec<_> = products
+                    .iter()
+                    .map(|product| format!("{vendor:04x}:{product:04x}"))
+                    .collect();
+                match ids.as_slice() {
+                    [id] => write!(f, "device {id} not found"),
+                    _ => write!(f, "no device found (scanned {})", ids.join(", ")),
+                }
+            }
+            ControlError::DeviceOpenFailed(rusb::Error::Access) => write!(
+                f,
+                "permission denied opening the tower (try `sudo`, or add a udev rule to grant access)"
+            ),
+            ControlError::DeviceOpenFailed(err) => {
+                write!(f, "failed to open the tower: {err}")
+            }
+            ControlError::SerialNotFound(serial) => {
+                write!(f, "no tower with serial number {serial} found")
+            }
+            ControlError::LocationNotFound(bus, address) => {
+                write!(f, "no tower at bus {bus:03} address {address:03}")
+            }
+            ControlError::AmbiguousDevice(count) => write!(
+                f,
+                "{count} towers attached and none selected; pass --serial (see `ptltectl list`) or --product-id"
+            ),
+            ControlError::DeviceDisconnected => write!(
+                f,
+                "the tower was unplugged (or reset) after it was opened; nothing was written"
+            ),
+            ControlError::InvalidArg(msg) => write!(f, "{msg}"),
+            ControlError::Usb {
+                op,
+                source: rusb::Error::Access,
+            } => write!(
+                f,
+                "permission denied during {op} on the tower (try `sudo`, or add a udev rule to grant access)"
+            ),
+            ControlError::Usb { op, source } => write!(f, "usb error during {op}: {source}"),
+            ControlError::ShortWrite { written, expected } => {
+                write!(f, "usb short write ({written} of {expected} bytes)")
+            }
+            ControlError::ShortRead => write!(f, "usb short read"),
+            ControlError::EndpointMissing(available) => {
+                let available: Vec<_> = available.iter().map(|a| format!("{a:#04x}")).collect();
+                write!(
+                    f,
+                    "no interrupt OUT endpoint {ENDPOINT_OUT:#04x} on interface 0 (found: {})",
+                    if available.is_empty() {
+                        "none".to_string()
+                    } else {
+                        available.join(", ")
+                    }
+                )
+            }
+            ControlError::FirmwareTooOld { found, required } => write!(
+                f,
+                "tower firmware {} is older than the required {}",
+                found.map_or_else(|| "unknown".into(), firmware_version),
+                firmware_version(*required)
+            ),
+            ControlError::ReadTimeout(timeout) => {
+                write!(f, "the tower sent no state report within {timeout:.0?}")
+            }
+            ControlError::ReadUnavailable(err) => write!(
+                f,
+                "could not read the tower state (read-back may be unsupported): {err}"
+            ),
+            ControlError::InvalidReport(report, problems) => write!(
+                f,
+                "{} invalid: {}",
+                format_report(report),
+                problems.join("; ")
+            ),
+            ControlError::ReportFile(path, err) => {
+                write!(f, "failed to write report file {}: {err}", path.display())
+            }
+            ControlError::File(path, err) => write!(f, "{}: {err}", path.display()),
+            ControlError::Config(msg) => write!(f, "config error: {msg}"),
+            ControlError::ScriptLine(line, err) => write!(f, "line {line}: {err}"),
+            ControlError::ScriptFailed(failures) => {
+                write!(f, "{} line(s) failed", failures.len())?;
+                for failure in failures {
+                    write!(f, "\n  {failure}")?;
+                }
+                Ok(())
+            }
+            ControlError::ProbeUnsafe {
+                report,
+                cause,
+                reset,
+            } => {
+                write!(
+                    f,
+                    "{} seems unsafe: the tower stopped answering ({cause}); ",
+                    format_report(report)
+                )?;
+                match reset {
+                    None => write!(f, "it was reset"),
+                    Some(err) => write!(f, "resetting it failed too: {err}"),
+                }
+            }
+            ControlError::Interrupted => write!(f, "interrupted"),
+            ControlError::HeldBy(pid, command) => write!(
+                f,
+                "the tower is held by `ptltectl {command}` (pid {pid}); stop it or send commands through it"
+            ),
+            ControlError::Daemon(pid, msg) => write!(f, "via pid {pid}: {msg}"),
+            ControlError::Io(err) => write!(f, "i/o error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+impl ControlError {
+    /// Bus hiccups worth retrying within `--retry-for`. A missing tower only
+    /// counts when the caller is waiting for one to appear.
+    pub fn is_transient(&self, wait_for_device: bool) -> bool {
+        match self {
+            ControlError::Usb { source, .. } | ControlError::DeviceOpenFailed(source) => matches!(
+                source,
+                rusb::Error::Busy
+                    | rusb::Error::Pipe
+                    | rusb::Error::Timeout
+                    | rusb::Error::NoDevice
+                    | rusb::Error::Io
+            ),
+            ControlError::ShortWrite { .. }
+            | ControlError::DeviceDisconnected
+            | ControlError::HeldBy(..) => true,
+            ControlError::DeviceNotFound(..) => wait_for_device,
+            _ => false,
+        }
+    }
+
+    /// Process exit status: 1 for most failures, with the ones scripts are
+    /// likely to branch on set apart.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ControlError::DeviceDisconnected => EXIT_DISCONNECTED,
+            _ => 1,
+        }
+    }
+
+    /// A next step for errors new users commonly hit, shown by
+    /// `--error-format human` and `json`.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            ControlError::DeviceNotFound(..) => {
+                Some("check the cable, then run `ptltectl list` to see which towers are attached")
+            }
+            ControlError::DeviceDisconnected => Some(
+                "plug it back in and retry; --retry-for 30s --wait-for-device rides out unplugs",
+            ),
+            ControlError::SerialNotFound(_) | ControlError::AmbiguousDevice(_) => {
+                Some("`ptltectl list` shows the serial number of every attached tower")
+            }
+            ControlError::LocationNotFound(..) => Some(
+                "`ptltectl list` shows where each tower sits; addresses change when it is replugged",
+            ),
+            ControlError::DeviceOpenFailed(rusb::Error::Access)
+            | ControlError::Usb {
+                source: rusb::Error::Access,
+                ..
+            } => Some(
+                "a udev rule such as SUBSYSTEM==\"usb\", ATTRS{idVendor}==\"191a\", MODE=\"0666\" grants access without sudo",
+            ),
+            ControlError::Usb {
+                source: rusb::Error::Busy,
+                ..
+            } => Some(
+                "another program or kernel driver holds the tower; stop it, or drop --no-auto-detach",
+            ),
+            ControlError::EndpointMissing(_) => {
+                Some("variants that take feature reports may work with --transfer control")
+            }
+            ControlError::ReadUnavailable(_) | ControlError::ReadTimeout(_) => {
+                Some("this tower may not report its state; commands that only write still work")
+            }
+            ControlError::FirmwareTooOld { .. } => {
+                Some("`ptltectl list` shows the firmware of every attached tower")
+            }
+            ControlError::ShortWrite { .. } => Some(
+                "firmware that acknowledges fewer bytes than it takes works with --accept-short",
+            ),
+            ControlError::InvalidReport(..) => {
+                Some("`ptltectl decode <BYTES> --explain-bits` shows what each field means")
+            }
+            _ => None,
+        }
+    }
+
+    /// Failures of the device or bus, which may clear up on their own or after
+    /// reopening, as opposed to bad input or local file problems.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            ControlError::DeviceNotFound(..)
+                | ControlError::DeviceOpenFailed(_)
+                | ControlError::SerialNotFound(_)
+                | ControlError::LocationNotFound(..)
+                | ControlError::DeviceDisconnected
+                | ControlError::Usb { .. }
+                | ControlError::ShortWrite { .. }
+                | ControlError::ShortRead
+                | ControlError::ReadUnavailable(_)
+                | ControlError::ReadTimeout(_)
+                | ControlError::HeldBy(..)
+        )
+    }
+}
+
+pub fn build_report(buzzer: u8, pitch: u8, led_ry: u8, led_gb: u8, led_w: u8) -> [u8; REPORT_LEN] {
+    [
+        COMMAND_VERSION,
+        COMMAND_ID,
+        buzzer,
+        pitch,
+        led_ry,
+        led_gb,
+        led_w,
+        0,
+    ]
+}
+
+pub fn assemble_leds(colors: &[u8], state: u8) -> ControlResult<(u8, u8, u8)> {
+    let state = try_nibble(state)?;
+    let (mut led_ry, mut led_gb, mut led_w) = (LED_KEEP_PAIR, LED_KEEP_PAIR, LED_KEEP_HIGH);
+    for (i, &color) in colors.iter().enumerate() {
+        if colors[..i].contains(&color) {
+            return Err(ControlError::InvalidArg(format!(
+                "color '{}' listed more than once",
+                color_name(color)
+            )));
+        }
+        match color {
+            COLOR_RED => led_ry = (state << 4) | nibble(led_ry),
+            COLOR_YELLOW => led_ry = (led_ry & 0xF0) | state,
+            COLOR_GREEN => led_gb = (state << 4) | nibble(led_gb),
+            COLOR_BLUE => led_gb = (led_gb & 0xF0) | state,
+            COLOR_WHITE => led_w = state << 4,
+            _ => return Err(ControlError::InvalidArg("color out of range".into())),
+        }
+    }
+    Ok((led_ry, led_gb, led_w))
+}
+
+/// Masks to the low nibble, for values a parser has already range-checked.
+pub fn nibble(value: u8) -> u8 {
+    value & 0x0F
+}
+
+/// `value` if it fits in a nibble, for builders that take values from code
+/// rather than from the parsers and must not silently truncate them.
+pub fn try_nibble(value: u8) -> ControlResult<u8> {
+    if value <= 0x0F {
+        Ok(value)
+    } else {
+        Err(ControlError::InvalidArg(format!(
+            "nibble {value:#x} out of range (0x0-0xF)"
+        )))
+    }
+}
+
+pub fn format_report(report: &[u8; REPORT_LEN]) -> String {
+    report
+        .iter()
+        .map(|byte| format!("{byte:#04x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn firmware_version(version: rusb::Version) -> String {
+    format!(
+        "{}.{}{}",
+        version.major(),
+        version.minor(),
+        version.sub_minor()
+    )
+}
+
+/// Display name of a color id, the inverse of `color_alias`.
+pub fn color_name(color: u8) -> &'static str {
+    COLOR_NAMES
+        .get(usize::from(color))
+        .copied()
+        .unwrap_or("unknown")
+}
+
+/// Lamp and buzzer fields of a report as bare nibbles, lamps indexed by color id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TowerState {
+    pub leds: [u8; COLOR_COUNT],
+    pub buzzer_pattern: u8,
+    pub buzzer_limit: u8,
+    pub pitch_a: u8,
+    pub pitch_b: u8,
+}
+
+impl TowerState {
+    /// The control report that puts a tower into this state.
+    pub fn to_report(&self) -> [u8; REPORT_LEN] {
+        let [red, yellow, green, blue, white] = self.leds;
+        build_report(
+            (self.buzzer_limit << 4) | self.buzzer_pattern,
+            (self.pitch_a << 4) | self.pitch_b,
+            (red << 4) | yellow,
+            (green << 4) | blue,
+            white << 4,
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        let leds = COLOR_NAMES
+            .iter()
+            .zip(self.leds)
+            .map(|(name, state)| format!("\"{name}\":\"{}\"", led_state_name(state)))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "{{{leds},\"buzzer\":\"{}\",\"limit\":{},\"pitch_a\":{},\"pitch_b\":{}}}",
+            led_state_name(self.buzzer_pattern),
+            self.buzzer_limit,
+            self.pitch_a,
+            self.pitch_b
+        )
+    }
+}
+
+impl fmt::Display for TowerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, state) in COLOR_NAMES.iter().zip(self.leds) {
+            write!(f, "{name}={} ", led_state_name(state))?;
+        }
+        write!(
+            f,
+            "buzzer={} limit={} pitch={:#x},{:#x}",
+            led_state_name(self.buzzer_pattern),
+            self.buzzer_limit,
+            self.pitch_a,
+            self.pitch_b
+        )
+    }
+}
+
+pub fn decode_report(report: &[u8; REPORT_LEN]) -> TowerState {
+    let [_, _, buzzer, pitch, led_ry, led_gb, led_w, _] = *report;
+    TowerState {
+        leds: [
+            led_ry >> 4,
+            nibble(led_ry),
+            led_gb >> 4,
+            nibble(led_gb),
+            led_w >> 4,
+        ],
+        buzzer_pattern: nibble(buzzer),
+        buzzer_limit: buzzer >> 4,
+        pitch_a: pitch >> 4,
+        pitch_b: nibble(pitch),
+    }
+}
+
+/// Short alias for an LED nibble, or its hex value when it has none. Buzzer
+/// pattern nibbles share the same numbering, so this names those too.
+pub fn led_state_name(state: u8) -> String {
+    match state {
+        0x0 => "off".into(),
+        0x1 => "on".into(),
+        0x2 => "pattern1".into(),
+        0x3 => "pattern2".into(),
+        0x4 => "pattern3".into(),
+        0x5 => "pattern4".into(),
+        LED_KEEP => "keep".into(),
+        other => format!("{other:#x}"),
+    }
+}
+
+/// A logical field of a control report, as laid out by `build_report`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Led(u8),
+    BuzzerPattern,
+    BuzzerLimit,
+    PitchA,
+    PitchB,
+}
+
+/// One field that differs between two reports, with its old and new nibble.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    pub field: Field,
+    pub from: u8,
+    pub to: u8,
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (from, to) = (self.from, self.to);
+        match self.field {
+            Field::Led(color) => write!(
+                f,
+                "{} {} -> {}",
+                color_name(color),
+                led_state_name(from),
+                led_state_name(to)
+            ),
+            Field::BuzzerPattern => write!(
+                f,
+                "buzzer {} -> {}",
+                led_state_name(from),
+                led_state_name(to)
+            ),
+            Field::BuzzerLimit => write!(f, "limit {from} -> {to}"),
+            Field::PitchA => write!(f, "pitch A {from:#x} -> {to:#x}"),
+            Field::PitchB => write!(f, "pitch B {from:#x} -> {to:#x}"),
+        }
+    }
+}
+
+/// The logical fields that differ from `a` to `b`, in report order. Framing
+/// bytes are not compared.
+pub fn report_diff(a: &[u8; REPORT_LEN], b: &[u8; REPORT_LEN]) -> Vec<FieldChange> {
+    let (a, b) = (decode_report(a), decode_report(b));
+    let leds =
+        (0..COLOR_COUNT).map(|color| (Field::Led(color as u8), a.leds[color], b.leds[color]));
+    let buzzer = [
+        (Field::BuzzerPattern, a.buzzer_pattern, b.buzzer_pattern),
+        (Field::BuzzerLimit, a.buzzer_limit, b.buzzer_limit),
+        (Field::PitchA, a.pitch_a, b.pitch_a),
+        (Field::PitchB, a.pitch_b, b.pitch_b),
+    ];
+    leds.chain(buzzer)
+        .filter(|(_, from, to)| from != to)
+        .map(|(field, from, to)| FieldChange { field, from, to })
+        .collect()
+}
+
+/// Lamp and buzzer states with a defined meaning: off, on and the four
+/// patterns.
+pub const DEFINED_STATES: [u8; 6] = [0x0, 0x1, 0x2, 0x3, 0x4, 0x5];
+
+/// Every combination of defined lamp states and buzzer patterns, generated
+/// lazily, red varying slowest and the buzzer fastest. A sounding buzzer runs
+/// continuously at the default pitches: limits and pitches would multiply the
+/// count without changing what the tower shows.
+pub fn possible_states() -> impl Iterator<Item = TowerState> {
+    let base = DEFINED_STATES.len();
+    (0..base.pow(COLOR_COUNT as u32 + 1)).map(move |mut index| {
+        let mut next = || {
+            let state = DEFINED_STATES[index % base];
+            index /= base;
+            state
+        };
+        let buzzer_pattern = next();
+        let mut leds = [LED_OFF; COLOR_COUNT];
+        for led in leds.iter_mut().rev() {
+            *led = next();
+        }
+        let (pitch_a, pitch_b) = if buzzer_pattern == BUZZER_OFF {
+            (PITCH_OFF, PITCH_OFF)
+        } else {
+            (BUZZER_PITCH_DEFAULT_A, BUZZER_PITCH_DEFAULT_B)
+        };
+        TowerState {
+            leds,
+            buzzer_pattern,
+            buzzer_limit: 0,
+            pitch_a,
+            pitch_b,
+        }
+    })
+}
+
+/// Named values accepted in place of a number, matched case-insensitively.
+/// These tables are the parsers' vocabulary and what `aliases` prints.
+pub type AliasTable = &'static [(&'static str, u8)];
+
+pub const LED_STATE_ALIASES: AliasTable = &[
+    ("led_off", 0x0),
+    ("off", 0x0),
+    ("led_on", 0x1),
+    ("on", 0x1),
+    ("solid", 0x1),
+    ("led_pattern1", 0x2),
+    ("pattern1", 0x2),
+    ("led_pattern2", 0x3),
+    ("pattern2", 0x3),
+    ("led_pattern3", 0x4),
+    ("pattern3", 0x4),
+    ("led_pattern4", 0x5),
+    ("pattern4", 0x5),
+    ("led_keep", LED_KEEP),
+    ("keep", LED_KEEP),
+];
+
+pub const BUZZER_ALIASES: AliasTable = &[
+    ("buzz_off", 0x0),
+    ("buzzer_off", 0x0),
+    ("off", 0x0),
+    ("buzz_on", 0x1),
+    ("buzzer_on", 0x1),
+    ("on", 0x1),
+    ("buzz_pattern1", 0x2),
+    ("pattern1", 0x2),
+    ("buzz_pattern2", 0x3),
+    ("pattern2", 0x3),
+    ("buzz_pattern3", 0x4),
+    ("pattern3", 0x4),
+    ("buzz_pattern4", 0x5),
+    ("pattern4", 0x5),
+    ("buzzer_keep", BUZZER_KEEP),
+    ("keep", BUZZER_KEEP),
+];
+
+pub fn lookup_alias(table: AliasTable, value: &str) -> Option<u8> {
+    table
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(value))
+        .map(|&(_, value)| value)
+}
+
+pub fn parse_led_state(value: &str) -> Result<u8, String> {
+    if let Some(alias) = led_state_alias(value) {
+        return Ok(alias);
+    }
+    parse_nibble(value)
+}
+
+pub fn led_state_alias(value: &str) -> Option<u8> {
+    lookup_alias(LED_STATE_ALIASES, value)
+}
+
+pub fn parse_buzzer_pattern(value: &str) -> Result<u8, String> {
+    if let Some(alias) = buzzer_alias(value) {
+        return Ok(alias);
+    }
+    parse_nibble(value)
+}
+
+pub fn buzzer_alias(value: &str) -> Option<u8> {
+    lookup_alias(BUZZER_ALIASES, value)
+}
+
+pub fn parse_nibble(value: &str) -> Result<u8, String> {
+    let num = parse_u8_any(value).map_err(|_| format!("invalid nibble '{value}'"))?;
+    if num <= 0x0F {
+        Ok(num)
+    } else {
+        Err(format!("nibble {num:#x} out of range (0x0-0xF)"))
+    }
+}
+
+pub fn parse_u8_any(value: &str) -> Result<u8, String> {
+    if let Some(hex) = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        u8::from_str_radix(hex, 16).map_err(|_| format!("invalid hex value '{value}'"))
+    } else {
+        value
+            .parse::<u8>()
+            .map_err(|_| format!("invalid number '{value}'"))
+    }
+}
+
+/// Sets all five lamps at once, red to white, leaving the buzzer as it is.
+pub fn tower_report(leds: [u8; COLOR_COUNT]) -> ControlResult<[u8; REPORT_LEN]> {
+    let [red, yellow, green, blue, white] = leds.map(try_nibble);
+    Ok(build_report(
+        BUZZER_KEEP,
+        0,
+        (red? << 4) | yellow?,
+        (green? << 4) | blue?,
+        white? << 4,
+    ))
+}
+
+/// Somewhere reports can be written: a claimed USB tower, a hidraw node, or
+/// a recorder standing in for the hardware in tests. Only `send` is
+/// required; the rest have defaults for transports that cannot read the
+/// tower back or do not know where it sits.
+pub trait Transport {
+    fn send(&mut self, report: &[u8; REPORT_LEN]) -> ControlResult<()>;
+
+    /// The state report the tower sends back, for models with read-back.
+    fn read_report(&mut self) -> ControlResult<[u8; REPORT_LEN]> {
+        Err(ControlError::InvalidArg(
+            "this transport cannot read the tower back".into(),
+        ))
+    }
+
+    /// `read_report`, giving up after `timeout` rather than the transport's
+    /// own, for callers that would rather do without the state than wait.
+    fn read_report_within(&mut self, _timeout: Duration) -> ControlResult<[u8; REPORT_LEN]> {
+        self.read_report()
+    }
+
+    /// Bus number and device address of the tower, when on USB.
+    fn location(&self) -> Option<(u8, u8)> {
+        None
+    }
+
+    /// Serial number of the tower, when it reports one.
+    fn serial(&self) -> Option<String> {
+        None
+    }
+
+    /// `bcdDevice` firmware version of the tower, when on USB.
+    fn firmware(&self) -> Option<rusb::Version> {
+        None
+    }
+
+    /// Opens the device now, for transports that otherwise wait for the
+    /// first write.
+    fn connect(&mut self) -> ControlResult<()> {
+        Ok(())
+    }
+
+    /// Lets go of the device so the next use opens it afresh.
+    fn disconnect(&mut self) {}
+}
+
+/// Setup packet fields for writing reports as control transfers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ControlSetup {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+}
+
+/// HID SET_REPORT of output report 0 to interface 0.
+pub const HID_SET_REPORT: ControlSetup = ControlSetup {
+    request_type: 0x21,
+    request: 0x09,
+    value: 0x0200,
+    index: 0,
+};
+
+/// How reports are written: to the interrupt OUT endpoint, as the LR6-USB
+/// takes them, or as a control transfer for feature-report firmware.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    Interrupt,
+    Control(ControlSetup),
+}
+
+/// Which tower `UsbTransport::open_with` picks and how it claims and writes
+/// to it. The default is the first supported tower, interface 0, the
+/// kernel driver detached, and the usual timeout and busy retries.
+#[derive(Clone, Debug)]
+pub struct UsbOptions {
+    pub vendor_id: u16,
+    /// Models to look for, preferred first.
+    pub product_ids: Vec<u16>,
+    /// Only the tower with this serial number, ignoring case and padding.
+    pub serial: Option<String>,
+    /// Only the tower at this bus number and device address.
+    pub location: Option<(u8, u8)>,
+    /// Fail with `AmbiguousDevice` rather than take the first of several
+    /// towers when none is selected.
+    pub strict: bool,
+    /// Reset the tower on the bus first, reopening it if it re-enumerates.
+    pub usb_reset: bool,
+    pub auto_detach: bool,
+    /// Interfaces to claim; 0 carries the reports and must be among them.
+    pub interfaces: Vec<u8>,
+    pub claim_retries: usize,
+    pub claim_retry_delay: Duration,
+    /// Per-transfer timeout; zero waits forever.
+    pub timeout: Duration,
+    pub transfer: Transfer,
+    /// Refuse towers whose `bcdDevice` is older than this.
+    pub min_firmware: Option<rusb::Version>,
+}
+
+impl Default for UsbOptions {
+    fn default() -> Self {
+        UsbOptions {
+            vendor_id: VENDOR_ID,
+            product_ids: SUPPORTED_PRODUCTS.to_vec(),
+            serial: None,
+            location: None,
+            strict: false,
+            usb_reset: false,
+            auto_detach: true,
+            interfaces: vec![0],
+            claim_retries: BUSY_RETRY_ATTEMPTS,
+            claim_retry_delay: Duration::from_millis(BUSY_RETRY_DELAY_MS),
+            timeout: Duration::from_millis(TIMEOUT_MS),
+            transfer: Transfer::Interrupt,
+            min_firmware: None,
+        }
+    }
+}
+
+/// Progress `UsbTransport::open_with` reports to its caller, which may log
+/// it; nothing here is fatal.
+pub enum UsbEvent<'a> {
+    /// This many towers matched and none was selected; the first that opens
+    /// is used.
+    Ambiguous(usize),
+    Opened {
+        bus: u8,
+        address: u8,
+    },
+    /// Enabling kernel-driver auto-detach, or `None` when it is turned off.
+    AutoDetach(Option<&'a rusb::Result<()>>),
+    Claim {
+        interface: u8,
+        attempt: usize,
+        attempts: usize,
+        result: &'a rusb::Result<()>,
+    },
+}
+
+/// How long each step of opening a tower took.
+#[derive(Clone, Debug)]
+pub struct SetupTiming {
+    /// Finding and opening the device; `None` for a handle passed in.
+    pub open: Option<Duration>,
+    pub detach: Duration,
+    pub claim: Duration,
+    /// Claim attempts over all interfaces, busy retries included.
+    pub claim_attempts: usize,
+}
+
+impl fmt::Display for SetupTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(open) = self.open {
+            write!(f, "open={open:.2?} ")?;
+        }
+        write!(
+            f,
+            "detach={:.2?} claim={:.2?} ({} attempt{})",
+            self.detach,
+            self.claim,
+            self.claim_attempts,
+            if self.claim_attempts == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// A tower on USB with its interfaces claimed (the kernel driver detached
+/// where supported) until it is dropped.
+pub struct UsbTransport<T: UsbContext = rusb::Context> {
+    handle: rusb::DeviceHandle<T>,
+    serial: Option<String>,
+    timeout: Duration,
+    transfer: Transfer,
+    setup: SetupTiming,
+}
+
+impl UsbTransport {
+    /// The first attached tower of any supported model, with the defaults
+    /// of `UsbOptions`.
+    pub fn open() -> ControlResult<Self> {
+        UsbTransport::open_with(&UsbOptions::default(), |_| {})
+    }
+
+    /// Finds the tower `options` selects in a fresh USB context and claims
+    /// it, telling `on_event` how it goes.
+    pub fn open_with(
+        options: &UsbOptions,
+        mut on_event: impl FnMut(UsbEvent),
+    ) -> ControlResult<Self> {
+        let started = Instant::now();
+        let context = usb_context()?;
+        let mut handle = open_device(&context, options, &mut on_event)?;
+        let device = handle.device();
+        on_event(UsbEvent::Opened {
+            bus: device.bus_number(),
+            address: device.address(),
+        });
+        if options.usb_reset {
+            // A reset that re-enumerates the tower invalidates the handle, so
+            // give it a moment to come back and open it afresh.
+            match handle.reset() {
+                Ok(()) => {}
+                Err(rusb::Error::NotFound) => {
+                    drop(handle);
+                    thread::sleep(Duration::from_millis(USB_RESET_SETTLE_MS));
+                    handle = open_device(&context, options, &mut on_event)?;
+                }
+                Err(err) => return Err(usb_error("reset")(err)),
+            }
+        }
+        let opened = started.elapsed();
+
+        let mut transport = UsbTransport::claim(handle, options, on_event)?;
+        transport.setup.open = Some(opened);
+        Ok(transport)
+    }
+}
+
+impl<T: UsbContext> UsbTransport<T> {
+    /// Takes over a handle the caller has already opened, for hosts that run
+    /// their own USB context or device discovery, and writes with `timeout`.
+    ///
+    /// The handle is owned from here on: interface 0 is claimed (detaching
+    /// the kernel driver where supported, retrying while another process
+    /// holds it) and released again when the transport is dropped, so the
+    /// caller must not claim that interface elsewhere while it is alive. The
+    /// handle keeps its context alive, so no separate lifetime needs to be
+    /// managed.
+    ///
+    /// ```no_run
+    /// use ptltectl::{Transport, UsbTransport, VENDOR_ID, PRODUCT_ID};
+    /// use std::time::Duration;
+    ///
+    /// let handle = rusb::open_device_with_vid_pid(VENDOR_ID, PRODUCT_ID).expect("no tower");
+    /// let mut tower = UsbTransport::with_handle(handle, Duration::from_millis(500))?;
+    /// tower.send(&[0, 0, 0, 0, 0, 0, 0, 0])?;
+    /// # Ok::<(), ptltectl::ControlError>(())
+    /// ```
+    pub fn with_handle(handle: rusb::DeviceHandle<T>, timeout: Duration) -> ControlResult<Self> {
+        let options = UsbOptions {
+            timeout,
+            ..UsbOptions::default()
+        };
+        UsbTransport::claim(handle, &options, |_| {})
+    }
+
+    fn claim(
+        mut handle: rusb::DeviceHandle<T>,
+        options: &UsbOptions,
+        mut on_event: impl FnMut(UsbEvent),
+    ) -> ControlResult<Self> {
+        let descriptor = handle.device().device_descriptor().ok();
+        if let Some(required) = options.min_firmware {
+            let found = descriptor
+                .as_ref()
+                .map(|descriptor| descriptor.device_version());
+            if found.is_none_or(|found| found < required) {
+                return Err(ControlError::FirmwareTooOld { found, required });
+            }
+        }
+        let serial = descriptor
+            .and_then(|descriptor| handle.read_serial_number_string_ascii(&descriptor).ok());
+
+        // Detaching makes some hubs re-enumerate the tower mid-command; users
+        // who unbound the driver themselves can skip it.
+        let started = Instant::now();
+        if options.auto_detach {
+            let result = handle.set_auto_detach_kernel_driver(true);
+            on_event(UsbEvent::AutoDetach(Some(&result)));
+        } else {
+            on_event(UsbEvent::AutoDetach(None));
+        }
+        let detach = started.elapsed();
+
+        // rusb releases every claimed interface when the handle is dropped.
+        let started = Instant::now();
+        let mut claim_attempts = 0;
+        for &interface in &options.interfaces {
+            claim_attempts += claim_interface_with_retry(
+                &mut handle,
+                interface,
+                options.claim_retries,
+                options.claim_retry_delay,
+                |attempt, attempts, result| {
+                    on_event(UsbEvent::Claim {
+                        interface,
+                        attempt,
+                        attempts,
+                        result,
+                    })
+                },
+            )?;
+        }
+        let claim = started.elapsed();
+        if options.transfer == Transfer::Interrupt {
+            check_out_endpoint(&handle, 0)?;
+        }
+
+        Ok(UsbTransport {
+            handle,
+            serial,
+            timeout: options.timeout,
+            transfer: options.transfer,
+            setup: SetupTiming {
+                open: None,
+                detach,
+                claim,
+                claim_attempts,
+            },
+        })
+    }
+
+    pub fn handle(&self) -> &rusb::DeviceHandle<T> {
+        &self.handle
+    }
+
+    /// How long opening and claiming took.
+    pub fn setup_timing(&self) -> &SetupTiming {
+        &self.setup
+    }
+}
+
+impl<T: UsbContext> Transport for UsbTransport<T> {
+    fn send(&mut self, report: &[u8; REPORT_LEN]) -> ControlResult<()> {
+        let timeout = self.timeout;
+        let written = match self.transfer {
+            Transfer::Interrupt => self.handle.write_interrupt(ENDPOINT_OUT, report, timeout),
+            Transfer::Control(setup) => self.handle.write_control(
+                setup.request_type,
+                setup.request,
+                setup.value,
+                setup.index,
+                report,
+                timeout,
+            ),
+        }
+        .map_err(write_error)?;
+        if written != REPORT_LEN {
+            return Err(ControlError::ShortWrite {
+                written,
+                expected: REPORT_LEN,
+            });
+        }
+        Ok(())
+    }
+
+    fn read_report(&mut self) -> ControlResult<[u8; REPORT_LEN]> {
+        self.read_report_within(self.timeout)
+    }
+
+    fn read_report_within(&mut self, timeout: Duration) -> ControlResult<[u8; REPORT_LEN]> {
+        let mut report = [0u8; REPORT_LEN];
+        let read = self
+            .handle
+            .read_interrupt(ENDPOINT_IN, &mut report, timeout)
+            .map_err(|err| match err {
+                rusb::Error::Timeout => ControlError::ReadTimeout(timeout),
+                err => ControlError::ReadUnavailable(err),
+            })?;
+        if read != REPORT_LEN {
+            return Err(ControlError::ShortRead);
+        }
+        Ok(report)
+    }
+
+    fn location(&self) -> Option<(u8, u8)> {
+        let device = self.handle.device();
+        Some((device.bus_number(), device.address()))
+    }
+
+    fn serial(&self) -> Option<String> {
+        self.serial.clone()
+    }
+
+    fn firmware(&self) -> Option<rusb::Version> {
+        let descriptor = self.handle.device().device_descriptor().ok()?;
+        Some(descriptor.device_version())
+    }
+}
+
+/// Writes `report` to the first attached tower, opening and releasing it
+/// around the one write. Hosts that write repeatedly should keep a
+/// `UsbTransport` instead, opened with `UsbTransport::open_with` to select
+/// a tower.
+pub fn send_report(report: &[u8; REPORT_LEN]) -> ControlResult<()> {
+    UsbTransport::open()?.send(report)
+}
+
+/// Maps a failed report write, telling an unplugged tower apart from other
+/// bus errors.
+pub fn write_error(err: rusb::Error) -> ControlError {
+    match err {
+        rusb::Error::NoDevice => ControlError::DeviceDisconnected,
+        err => usb_error("write")(err),
+    }
+}
+
+pub fn usb_context() -> ControlResult<rusb::Context> {
+    rusb::Context::new().map_err(usb_error("libusb init"))
+}
+
+/// Labels a USB failure with the operation that hit it, for `map_err`.
+pub fn usb_error(op: &'static str) -> impl Fn(rusb::Error) -> ControlError {
+    move |source| ControlError::Usb { op, source }
+}
+
+pub fn is_patlite(descriptor: &rusb::DeviceDescriptor, vendor: u16, products: &[u16]) -> bool {
+    descriptor.vendor_id() == vendor && products.contains(&descriptor.product_id())
+}
+
+/// Every attached tower of the given products, in preference order so the
+/// primary model comes first when different ones are attached together.
+/// `vendor` is normally `VENDOR_ID`; compatible models may use another.
+pub fn find_devices<T: UsbContext>(
+    context: &T,
+    vendor: u16,
+    products: &[u16],
+) -> ControlResult<Vec<rusb::Device<T>>> {
+    let devices = context.devices().map_err(usb_error("enumeration"))?;
+    let mut found = Vec::new();
+    for &product in products {
+        for device in devices.iter() {
+            let Ok(descriptor) = device.device_descriptor() else {
+                continue;
+            };
+            if is_patlite(&descriptor, vendor, &[product]) {
+                found.push(device);
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Opens the tower `options` selects by serial or location, or else the
+/// first one that opens. Several unselected towers fail under `strict` and
+/// are otherwise reported as `UsbEvent::Ambiguous`.
+fn open_device<T: UsbContext>(
+    context: &T,
+    options: &UsbOptions,
+    on_event: &mut impl FnMut(UsbEvent),
+) -> ControlResult<rusb::DeviceHandle<T>> {
+    let candidates = find_devices(context, options.vendor_id, &options.product_ids)?;
+    if candidates.is_empty() {
+        return Err(ControlError::DeviceNotFound(
+            options.vendor_id,
+            options.product_ids.clone(),
+        ));
+    }
+
+    if let Some(serial) = &options.serial {
+        return open_by_serial(&candidates, serial);
+    }
+    if let Some((bus, address)) = options.location {
+        let device = candidates
+            .iter()
+            .find(|device| device.bus_number() == bus && device.address() == address)
+            .ok_or(ControlError::LocationNotFound(bus, address))?;
+        return device.open().map_err(ControlError::DeviceOpenFailed);
+    }
+
+    if candidates.len() > 1 {
+        if options.strict {
+            return Err(ControlError::AmbiguousDevice(candidates.len()));
+        }
+        on_event(UsbEvent::Ambiguous(candidates.len()));
+    }
+
+    let mut last_open_error = rusb::Error::Other;
+    for device in &candidates {
+        match device.open() {
+            Ok(handle) => return Ok(handle),
+            Err(err) => last_open_error = err,
+        }
+    }
+    Err(ControlError::DeviceOpenFailed(last_open_error))
+}
+
+fn open_by_serial<T: UsbContext>(
+    candidates: &[rusb::Device<T>],
+    serial: &str,
+) -> ControlResult<rusb::DeviceHandle<T>> {
+    let mut last_open_error = None;
+    for device in candidates {
+        let descriptor = device
+            .device_descriptor()
+            .map_err(usb_error("descriptor read"))?;
+        let handle = match device.open() {
+            Ok(handle) => handle,
+            Err(err) => {
+                last_open_error = Some(err);
+                continue;
+            }
+        };
+        // Some firmware pads its serial or reports it in another case.
+        if let Ok(found) = handle.read_serial_number_string_ascii(&descriptor)
+            && found.trim().eq_ignore_ascii_case(serial.trim())
+        {
+            return Ok(handle);
+        }
+    }
+    // A tower that could not be opened may be the one asked for, so report
+    // that rather than claiming the serial is absent.
+    match last_open_error {
+        Some(err) => Err(ControlError::DeviceOpenFailed(err)),
+        None => Err(ControlError::SerialNotFound(serial.to_string())),
+    }
+}
+
+/// Claims `interface`, trying again up to `retries` more times `delay` apart
+/// while another process holds it, and returns how many attempts it took.
+/// `BUSY_RETRY_ATTEMPTS` and `BUSY_RETRY_DELAY_MS` are the usual values.
+/// `on_attempt` is told each attempt's number, the most there can be, and
+/// its result, for callers that log progress.
+pub fn claim_interface_with_retry<T: UsbContext>(
+    handle: &mut rusb::DeviceHandle<T>,
+    interface: u8,
+    retries: usize,
+    delay: Duration,
+    mut on_attempt: impl FnMut(usize, usize, &rusb::Result<()>),
+) -> ControlResult<usize> {
+    let strategy = Fixed::from(delay).take(retries);
+    let mut attempts = 0;
+    retry(strategy, || {
+        attempts += 1;
+        let result = handle.claim_interface(interface);
+        on_attempt(attempts, retries + 1, &result);
+        match result {
+            Ok(()) => OperationResult::Ok(()),
+            Err(rusb::Error::Busy) => OperationResult::Retry(rusb::Error::Busy),
+            Err(err) => OperationResult::Err(err),
+        }
+    })
+    .map_err(|err| usb_error("claim")(err.error))?;
+    Ok(attempts)
+}
+
+/// Fails with the endpoints that do exist when `interface` has no interrupt
+/// OUT endpoint at `ENDPOINT_OUT`, rather than letting the first write come
+/// back as an opaque `NotFound`.
+pub fn check_out_endpoint<T: UsbContext>(
+    handle: &rusb::DeviceHandle<T>,
+    interface: u8,
+) -> ControlResult<()> {
+    let config = handle
+        .device()
+        .active_config_descriptor()
+        .map_err(usb_error("config descriptor read"))?;
+    let mut available = Vec::new();
+    for descriptor in config
+        .interfaces()
+        .filter(|candidate| candidate.number() == interface)
+        .flat_map(|interface| interface.descriptors())
+    {
+        for endpoint in descriptor.endpoint_descriptors() {
+            if endpoint.address() == ENDPOINT_OUT
+                && endpoint.direction() == rusb::Direction::Out
+                && endpoint.transfer_type() == rusb::TransferType::Interrupt
+            {
+                return Ok(());
+            }
+            available.push(endpoint.address());
+        }
+    }
+    Err(ControlError::EndpointMissing(available))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_nibble_accepts_the_top_nibble() {
+        assert_eq!(try_nibble(0x0F).unwrap(), 0x0F);
+    }
+
+    #[test]
+    fn try_nibble_rejects_values_above_a_nibble() {
+        for value in [0x10, 0xFF] {
+            assert!(
+                matches!(try_nibble(value), Err(ControlError::InvalidArg(_))),
+                "{value:#x} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn report_diff_finds_one_changed_lamp() {
+        let off = [0x00; REPORT_LEN];
+        let mut yellow = off;
+        yellow[4] = 0x01;
+        assert_eq!(
+            report_diff(&off, &yellow),
+            [FieldChange {
+                field: Field::Led(COLOR_YELLOW),
+                from: LED_OFF,
+                to: LED_ON,
+            }]
+        );
+    }
+
+    #[test]
+    fn report_diff_lists_every_change_in_report_order() {
+        let from = [0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00];
+        let to = [0x00, 0x00, 0x31, 0xEF, 0x00, 0x02, 0x10, 0x00];
+        assert_eq!(
+            report_diff(&from, &to),
+            [
+                FieldChange {
+                    field: Field::Led(COLOR_RED),
+                    from: LED_ON,
+                    to: LED_OFF,
+                },
+                FieldChange {
+                    field: Field::Led(COLOR_BLUE),
+                    from: LED_OFF,
+                    to: 0x2,
+                },
+                FieldChange {
+                    field: Field::Led(COLOR_WHITE),
+                    from: LED_OFF,
+                    to: LED_ON,
+                },
+                FieldChange {
+                    field: Field::BuzzerPattern,
+                    from: BUZZER_OFF,
+                    to: BUZZER_ON,
+                },
+                FieldChange {
+                    field: Field::BuzzerLimit,
+                    from: 0,
+                    to: 3,
+                },
+                FieldChange {
+                    field: Field::PitchA,
+                    from: 0x0,
+                    to: 0xE,
+                },
+                FieldChange {
+                    field: Field::PitchB,
+                    from: 0x0,
+                    to: 0xF,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn possible_states_cover_every_combination_in_order() {
+        let states: Vec<_> = possible_states().collect();
+        assert_eq!(states.len(), DEFINED_STATES.len().pow(6));
+        assert_eq!(
+            states.first().unwrap().to_report(),
+            [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            states[1].to_report(),
+            [0x00, 0x00, 0x01, 0xEF, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            states.last().unwrap().to_report(),
+            [0x00, 0x00, 0x05, 0xEF, 0x55, 0x55, 0x50, 0x00]
+        );
+    }
+
+    #[test]
+    fn report_diff_ignores_framing_bytes() {
+        let mut framed = [0x00; REPORT_LEN];
+        framed[0] = 0x01;
+        framed[7] = 0xFF;
+        assert!(report_diff(&[0x00; REPORT_LEN], &framed).is_empty());
+    }
+}
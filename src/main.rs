@@ -1,46 +1,169 @@
-use clap::{Parser, Subcommand};
-use retry::{OperationResult, delay::Fixed, retry};
+mod config;
+
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use config::Config;
+use ptltectl::*;
+use retry::{
+    OperationResult,
+    delay::{Exponential, jitter},
+    retry,
+};
 use rusb::UsbContext;
+use rustyline::{DefaultEditor, error::ReadlineError};
 use std::fmt;
-use std::time::Duration;
-
-const VENDOR_ID: u16 = 0x191a;
-const PRODUCT_ID: u16 = 0x8003;
-const ENDPOINT_OUT: u8 = 0x01;
-const TIMEOUT_MS: u64 = 1000;
-const REPORT_LEN: usize = 8;
-// Allow concurrent commands ~1s for the kernel to release the HID interface.
-const BUSY_RETRY_ATTEMPTS: usize = 20;
-const BUSY_RETRY_DELAY_MS: u64 = 50;
-
-const COMMAND_VERSION: u8 = 0x00;
-const COMMAND_ID: u8 = 0x00;
-
-const COLOR_RED: u8 = 0;
-const COLOR_YELLOW: u8 = 1;
-const COLOR_GREEN: u8 = 2;
-const COLOR_BLUE: u8 = 3;
-const COLOR_WHITE: u8 = 4;
-
-const LED_KEEP: u8 = 0x0F;
-const LED_OFF: u8 = 0x00;
-const LED_KEEP_PAIR: u8 = (LED_KEEP << 4) | LED_KEEP;
-const LED_KEEP_HIGH: u8 = LED_KEEP << 4;
-
-const BUZZER_KEEP: u8 = 0x0F;
-const BUZZER_OFF: u8 = 0x00;
-const BUZZER_PITCH_DEFAULT_A: u8 = 0x0E;
-const BUZZER_PITCH_DEFAULT_B: u8 = 0x0F;
-const PITCH_OFF: u8 = 0x00;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// USB/IP carries every transfer over the network; allow for the round trip.
+const REMOTE_TIMEOUT_MS: u64 = 5000;
+// Backoff for --retry-for: doubling from the base, capped per attempt.
+const RETRY_FOR_BASE_MS: u64 = 50;
+const RETRY_FOR_MAX_DELAY_MS: u64 = 2000;
+// How long blink and melody wait to read the state they restore on Ctrl-C;
+// towers without read-back never answer, and should not delay the start.
+const PRIOR_STATE_TIMEOUT_MS: u64 = 200;
 
 const COLOR_HELP: &str = "Color id or alias: 0-4 | red yellow green blue white";
+const COLOR_LIST_HELP: &str =
+    "Comma-separated color ids or aliases sharing the state: 0-4 | red yellow green blue white";
+const CSS_NAME_HELP: &str =
+    "CSS color name (orange, lime, cyan, ...), lighting the lamp closest to it";
+const INDEX_HELP: &str = "Zero-based color index (red=0 .. white=4), for scripted loops";
 const LED_HELP: &str = "LED state nibble or alias: 0-15 | led_off led_on led_pattern1 led_pattern2 led_pattern3 led_pattern4 led_keep";
 const BUZZER_HELP: &str = "Buzzer pattern nibble or alias: 0-15 | buzz_off buzz_on buzz_pattern1 buzz_pattern2 buzz_pattern3 buzz_pattern4 buzzer_keep";
 const LIMIT_HELP: &str = "Buzzer limit nibble: 0=continuous, 1-15=timed";
 const PITCH_HELP: &str = "Pitch nibble (0-15) - supply A and B together to override defaults";
+const PITCH_A_FLAG_HELP: &str =
+    "Pitch A nibble on its own; B keeps its default unless --pitch-b is given";
+const PITCH_B_FLAG_HELP: &str =
+    "Pitch B nibble on its own; A keeps its default unless --pitch-a is given";
+const PITCH_BYTE_HELP: &str =
+    "Whole pitch byte (high nibble A, low nibble B) instead of separate nibbles";
 const REPORT_HELP: &str = "8-byte HID report (decimal or 0x-prefixed hex values)";
-
-type ControlResult<T> = Result<T, ControlError>;
+const PROBE_SAFE_HELP: &str = "Read the tower back right after writing, and reset it if the read times out (skipped on towers without read-back)";
+const INTERVAL_HELP: &str = "Milliseconds between state reads";
+const JSON_HELP: &str = "Emit one JSON object per line instead of text";
+const KEEP_BUZZER_HELP: &str =
+    "Turn the LEDs off but leave the buzzer (including a timed one) as it is";
+const FIFO_PATH_HELP: &str = "Named pipe to read command lines from; created if missing";
+const SOCKET_PATH_HELP: &str =
+    "Where to create the socket; a stale one left by a previous run is replaced";
+const RESET_ON_EXIT_HELP: &str = "Turn everything off when leaving the prompt";
+const ASSUME_DEVICE_HELP: &str =
+    "Do not touch USB: print each assembled report with its validation verdict";
+const DRY_RUN_HELP: &str =
+    "Do not touch USB: print each assembled report as hex and succeed, valid or not";
+const TIMING_HELP: &str = "Print how long open, detach, claim and write took to stderr";
+const EASE_HELP: &str = "How the dwell time is spread across the pattern steps";
+const PULSE_DURATION_HELP: &str = "Total time from off to solid";
+const CONFIG_HELP: &str = "Config file; repeat to layer several, later ones overriding (default: $PTLTECTL_CONFIG_PATH, else ~/.config/ptltectl/config.toml)";
+const COLOR_ALIAS_FILE_HELP: &str = "File of extra color names, one `name = \"color\"` line each (e.g. error = \"red\"), usable wherever a color is";
+const YES_HELP: &str = "Skip the confirmation that `confirm_reset = true` in the config asks for";
+const LIST_JSON_HELP: &str = "Emit a JSON array of devices instead of text";
+const HEARTBEAT_INTERVAL_HELP: &str = "Milliseconds from one beat to the next";
+const HEARTBEAT_ON_HELP: &str = "Milliseconds the lamp stays lit on each beat";
+const BLINK_PERIOD_HELP: &str = "Milliseconds between switching the lamp on and off";
+const BLINK_COUNT_HELP: &str = "Times to light the lamp";
+const MELODY_HELP: &str = "Notes as PITCH:MILLIS, comma-separated, e.g. 14:200,15:200,14:400; PITCH is the buzzer's pitch A nibble (0-15)";
+const CONTINUE_ON_ERROR_HELP: &str =
+    "Keep going after a failing line and report every failure at the end";
+const CVD_HELP: &str = "Color-vision-deficiency mode: show solid lamps with a per-color blink pattern (red=pattern1 yellow=pattern2 green=solid blue=pattern3 white=pattern4, configurable under [cvd])";
+const MIN_FIRMWARE_HELP: &str =
+    "Refuse towers whose firmware (bcdDevice, e.g. 1.20 or 0x0120; see `list`) is older than this";
+const FORMAT_HELP: &str = "How results are printed on stdout: status lines, or one JSON object per result (errors included, as {\"status\":\"error\",...})";
+const ERROR_FORMAT_HELP: &str = "How errors are printed on stderr: plain text, colored with a hint for common problems, or the same JSON object as --format json";
+const VENDOR_ID_HELP: &str = "USB vendor id to look for (decimal or 0x-prefixed hex), for compatible towers sold under another id";
+const PRODUCT_ID_HELP: &str =
+    "Only use towers with this USB product id (default: any supported model, 0x8003 first)";
+const SERIAL_HELP: &str =
+    "Use the tower with this USB serial number (see `list`), ignoring case and surrounding spaces";
+const BUS_HELP: &str = "Use the tower on this USB bus (with --address; see `list`), for towers without a usable serial";
+const ADDRESS_HELP: &str = "Use the tower at this device address on --bus (see `list`)";
+const QUIET_HELP: &str = "Suppress status lines (ok, unchanged, valid) and warnings; errors, exit codes and requested data such as list or --json output are kept";
+const STRICT_HELP: &str =
+    "Fail instead of picking the first tower when several match and none was selected";
+const INTENSITY_HELP: &str = "Pseudo-volume in place of PATTERN and LIMIT: 1 sparse beeps .. 4 continuous (remap in [buzzer_intensity])";
+const LIMIT_FLAG_HELP: &str = "Buzzer limit with --intensity (default 0, continuous)";
+const BLOCK_HELP: &str = "Return only once a timed limit should have run out (one second per limit step); Ctrl-C silences early";
+const COOLDOWN_HELP: &str =
+    "Stay silent (exit 0) if this tower buzzed less than this many milliseconds ago";
+const STATES_JSON_HELP: &str = "Emit one JSON object per state, with its report, instead of text";
+const MINIMAL_HELP: &str = "Read the tower first and write only the lamps and buzzer that differ, skipping the write when none do (a full write when it cannot be read)";
+const INFO_JSON_HELP: &str = "Emit a JSON object instead of text";
+const ALIASES_JSON_HELP: &str = "Emit a JSON object of alias tables instead of text";
+const DETACH_INTERFACES_HELP: &str = "Interfaces to detach from the kernel and claim, for composite firmware (must include 0, which carries the reports)";
+const CLAIM_RETRIES_HELP: &str = "Times to retry claiming the interface while another process holds it; the longest wait is retries x --claim-retry-delay (0 tries once)";
+const CLAIM_RETRY_DELAY_HELP: &str = "Milliseconds between claim retries";
+const ACCEPT_SHORT_HELP: &str = "Warn instead of failing when the tower acknowledges fewer bytes than a full report, for firmware that under-reports the count";
+const NO_AUTO_DETACH_HELP: &str =
+    "Leave the kernel driver alone; claiming fails with Busy if one still holds the interface";
+const FOR_HELP: &str = "Clear what was set after this long, e.g. 5s, 500ms, 2m (also on Ctrl-C)";
+const SWAP_NIBBLES_HELP: &str = "Swap the high and low nibble of the red/yellow, green/blue and white bytes, for firmware that reads them the other way round";
+const BENCH_DURATION_HELP: &str = "How long to keep writing, e.g. 5s";
+const MIRROR_HELP: &str =
+    "Echo the lamps as colored blocks after each write and in monitor (terminals only)";
+const VERBOSE_HELP: &str = "After each write, name the tower (bus, address, serial) and the report it received, and trace opening, kernel-driver detach, claim attempts and the bytes written to stderr; -vv also traces the timeout and claim retry settings";
+const GRADIENT_DURATION_HELP: &str = "Time for one pass from --from to --to, e.g. 10s";
+const PREVIEW_HELP: &str =
+    "Print the effect as an ASCII timeline, one row per lamp, instead of driving the tower";
+const LOOP_HELP: &str = "Repeat the pass until interrupted";
+const REPORT_TEMPLATE_HELP: &str = "Print sent, validated and monitored reports with this layout, e.g. \"{red} {yellow} {green} {blue} {white} buzz={buzzer}\"; also {limit} {pitch_a} {pitch_b} {report}";
+const IF_PRESENT_HELP: &str =
+    "Only run the command if this file exists; otherwise exit 0 without touching the tower";
+const IF_CONTENT_HELP: &str =
+    "With --if-present, also require the file's content (trimmed) to equal this text";
+const INCLUDE_WHITE_HELP: &str = "Set the white lamp too (the default)";
+const EXCLUDE_WHITE_HELP: &str = "Leave the white lamp as it is";
+const SIMULATE_HELP: &str =
+    "Do not touch USB: act as if the tower accepted every write, or fail the way a real one would";
+const NAGIOS_STATE_HELP: &str = "Check result: OK, WARNING, CRITICAL or UNKNOWN";
+const NO_BUZZER_HELP: &str = "Keep the buzzer silent even for CRITICAL";
+const MERGE_CACHED_HELP: &str = "Fill kept lamps from a host-side cache of what was last sent to this tower, for firmware that forgets its state on reconnect (with --dry-run or --simulate, the cache of the --serial tower)";
+const EXEC_HELP: &str = "Shell command run on each change; {state}, {changes} and {json} are replaced with shell-quoted values";
+const MAX_RATE_HELP: &str =
+    "Most reports written per second; faster input waits instead of flooding the tower";
+const RETRY_FOR_HELP: &str = "Keep retrying a write that fails with a transient USB error (busy, pipe, timeout, unplugged) for up to this long, e.g. 30s";
+const WAIT_FOR_DEVICE_HELP: &str = "With --retry-for, also retry while no tower is attached";
+const EXPLAIN_HELP: &str =
+    "Before each write, print the `ptltectl report` command that sends the same bytes";
+const USB_RESET_HELP: &str = "Reset the tower on the bus before claiming it, to recover a wedged device; it may re-enumerate and is reopened";
+const COMPAT_CODE_HELP: &str =
+    "Five or six digits: red yellow green blue white [buzzer], each 0=off 1=on 2=blink 9=keep";
+const HIDRAW_HELP: &str = "Write reports to this Linux hidraw node (e.g. /dev/hidraw3) instead of through libusb: no detaching or claiming, only permission on the node";
+const REPORT_ID_HELP: &str = "Report id byte put in front of every report written with --hidraw";
+const TIMEOUT_HELP: &str = "USB transfer timeout in milliseconds, overriding the 1000 (5000 with --remote) default; 0 waits forever";
+const REMOTE_HELP: &str =
+    "Preset for towers imported over USB/IP: allow 5s per transfer instead of 1s";
+const VIA_DAEMON_HELP: &str = "Send one-shot commands through the `socket` or `fifo` server holding the tower, opening it directly when none does (`prefer_daemon = true` in the config does the same)";
+const STAGGER_HELP: &str = "Send each lamp as its own report this many milliseconds apart, for firmware that drops parts of a combined write (not atomic: lamps change one by one)";
+const EXPLAIN_BITS_HELP: &str =
+    "Print a byte-by-byte, nibble-by-nibble table of the fields instead of a summary";
+const SWEEP_BYTE_HELP: &str = "Index of the report byte to sweep (0-7)";
+const SWEEP_FROM_HELP: &str = "First value written (decimal or 0x-prefixed hex)";
+const SWEEP_TO_HELP: &str = "Last value written; below --from sweeps downwards";
+const SWEEP_STEP_HELP: &str = "Pause after each value, to watch what it does";
+const SWEEP_BASE_HELP: &str = "The other seven bytes, as a full 8-byte report (default: all zero)";
+const RESET_TO_BASE_HELP: &str = "Write the base report once the sweep ends or is interrupted";
+const BUTTON_BYTE_HELP: &str = "Index of the IN-report byte that carries the button (0-7)";
+const BUTTON_MASK_HELP: &str = "Bits of that byte that are set while the button is held";
+const BUTTON_EXEC_HELP: &str =
+    "Shell command run on each press and release; {event} becomes press or release";
+const DEBOUNCE_HELP: &str = "How long a new button level must hold before it counts";
+const SERVICE_MODE_HELP: &str = "Long-running subcommand the service runs";
+const SERVICE_OUTPUT_HELP: &str = "Write the unit to this file instead of stdout";
+const SERVICE_ARGS_HELP: &str = "Extra arguments for the subcommand, after `--`";
+const EMIT_SHELL_HELP: &str = "Before each write, print a shell command that sends the same report without ptltectl (hidapitester; printf to hidraw on Linux)";
+const EMIT_CODE_HELP: &str =
+    "Before each write, print the report as an array literal to paste into firmware code";
+const TRANSFER_HELP: &str = "How reports are written: to the interrupt OUT endpoint, or as a control transfer for feature-report firmware";
+const CONTROL_SETUP_HELP: &str = "Setup packet for --transfer control as REQUEST_TYPE,REQUEST,VALUE,INDEX (default: HID SET_REPORT 0x21,0x09,0x0200,0)";
+const EXIT_CODE_HELP: &str = "Print nothing (unless --verbose) and exit 2 while red is lit, 1 while yellow is, else 0; remap in [status_exit_codes]";
+const REPORT_FILE_HELP: &str =
+    "Append a timestamped line with the bytes of every sent report to this file";
 
 #[derive(Parser)]
 #[command(
@@ -50,18 +173,175 @@ type ControlResult<T> = Result<T, ControlError>;
     about = "Control the Patlite LR6-USB tower"
 )]
 struct Cli {
+    #[arg(long, global = true, value_name = "PATH", help = CONFIG_HELP)]
+    config: Vec<PathBuf>,
+    #[arg(long, global = true, value_name = "FILE", help = COLOR_ALIAS_FILE_HELP)]
+    color_alias_file: Option<PathBuf>,
+    #[arg(long, global = true, value_name = "FILE", help = IF_PRESENT_HELP)]
+    if_present: Option<PathBuf>,
+    #[arg(long, global = true, requires = "if_present", value_name = "TEXT", help = IF_CONTENT_HELP)]
+    if_content: Option<String>,
+    #[arg(long, global = true, value_name = "PATH", help = REPORT_FILE_HELP)]
+    report_file: Option<PathBuf>,
+    #[arg(long, global = true, value_name = "TEMPLATE", help = REPORT_TEMPLATE_HELP)]
+    report_template: Option<String>,
+    #[arg(long, global = true, help = ASSUME_DEVICE_HELP)]
+    assume_device: bool,
+    #[arg(long, global = true, conflicts_with = "assume_device", help = DRY_RUN_HELP)]
+    dry_run: bool,
+    #[arg(long, global = true, value_enum, conflicts_with_all = ["assume_device", "dry_run"], value_name = "RESULT", help = SIMULATE_HELP)]
+    simulate: Option<Simulate>,
+    #[arg(long, global = true, help = TIMING_HELP)]
+    timing: bool,
+    #[arg(long, global = true, help = NO_AUTO_DETACH_HELP)]
+    no_auto_detach: bool,
+    #[arg(long, global = true, default_value_t = BUSY_RETRY_ATTEMPTS, value_name = "N", help = CLAIM_RETRIES_HELP)]
+    claim_retries: usize,
+    #[arg(long = "claim-retry-delay", global = true, default_value_t = BUSY_RETRY_DELAY_MS, value_name = "MILLIS", help = CLAIM_RETRY_DELAY_HELP)]
+    claim_retry_delay_ms: u64,
+    #[arg(long, global = true, help = ACCEPT_SHORT_HELP)]
+    accept_short: bool,
+    #[arg(long, global = true, value_delimiter = ',', default_value = "0", value_name = "LIST", help = DETACH_INTERFACES_HELP)]
+    detach_interfaces: Vec<u8>,
+    #[arg(long, global = true, help = USB_RESET_HELP)]
+    usb_reset: bool,
+    #[arg(long, global = true, value_enum, default_value_t = TransferArg::Interrupt, help = TRANSFER_HELP)]
+    transfer: TransferArg,
+    #[arg(long, global = true, value_parser = parse_control_setup, value_name = "SETUP", help = CONTROL_SETUP_HELP)]
+    control_setup: Option<ControlSetup>,
+    #[arg(long, global = true, value_name = "PATH", help = HIDRAW_HELP)]
+    hidraw: Option<PathBuf>,
+    #[arg(long, global = true, value_parser = parse_byte, default_value = "0", requires = "hidraw", value_name = "BYTE", help = REPORT_ID_HELP)]
+    report_id: u8,
+    #[arg(long = "timeout", global = true, value_name = "MILLIS", help = TIMEOUT_HELP)]
+    timeout_ms: Option<u64>,
+    #[arg(long, global = true, help = REMOTE_HELP)]
+    remote: bool,
+    #[arg(long, global = true, help = VIA_DAEMON_HELP)]
+    via_daemon: bool,
+    #[arg(long, global = true, value_parser = parse_duration, value_name = "DURATION", help = RETRY_FOR_HELP)]
+    retry_for: Option<Duration>,
+    #[arg(long, global = true, requires = "retry_for", help = WAIT_FOR_DEVICE_HELP)]
+    wait_for_device: bool,
+    #[arg(long, global = true, help = SWAP_NIBBLES_HELP)]
+    swap_nibbles: bool,
+    #[arg(long, global = true, help = MERGE_CACHED_HELP)]
+    merge_cached: bool,
+    #[arg(long, global = true, help = MIRROR_HELP)]
+    mirror: bool,
+    #[arg(long, global = true, help = EXPLAIN_HELP)]
+    explain: bool,
+    #[arg(long, global = true, value_enum, value_name = "LANG", help = EMIT_CODE_HELP)]
+    emit_code: Option<CodeLanguage>,
+    #[arg(long, global = true, help = EMIT_SHELL_HELP)]
+    emit_shell: bool,
+    #[arg(long, global = true, help = CVD_HELP)]
+    cvd: bool,
+    #[arg(long, global = true, value_parser = parse_u16_any, default_value = "0x191a", value_name = "VID", help = VENDOR_ID_HELP)]
+    vendor_id: u16,
+    #[arg(long, global = true, value_parser = parse_u16_any, value_name = "PID", help = PRODUCT_ID_HELP)]
+    product_id: Option<u16>,
+    #[arg(long, global = true, value_parser = parse_firmware_version, value_name = "BCD", help = MIN_FIRMWARE_HELP)]
+    min_firmware: Option<rusb::Version>,
+    #[arg(long, global = true, value_name = "SERIAL", help = SERIAL_HELP)]
+    serial: Option<String>,
+    #[arg(long, global = true, requires = "address", value_name = "BUS", help = BUS_HELP)]
+    bus: Option<u8>,
+    #[arg(long, global = true, requires = "bus", value_name = "ADDRESS", help = ADDRESS_HELP)]
+    address: Option<u8>,
+    #[arg(short, long, global = true, help = QUIET_HELP)]
+    quiet: bool,
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet", help = VERBOSE_HELP)]
+    verbose: u8,
+    #[arg(long, global = true, help = STRICT_HELP)]
+    strict: bool,
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Plain, help = ERROR_FORMAT_HELP)]
+    error_format: ErrorFormat,
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text, help = FORMAT_HELP)]
+    format: OutputFormat,
     #[command(subcommand)]
     command: Command,
 }
 
+struct SendOptions {
+    report_file: Option<PathBuf>,
+    report_template: Option<String>,
+    assume_device: bool,
+    dry_run: bool,
+    simulate: Option<Simulate>,
+    timing: bool,
+    accept_short: bool,
+    hidraw: Option<PathBuf>,
+    report_id: u8,
+    retry_for: Option<Duration>,
+    wait_for_device: bool,
+    swap_nibbles: bool,
+    merge_cached: bool,
+    mirror: bool,
+    explain: bool,
+    emit_code: Option<CodeLanguage>,
+    emit_shell: bool,
+    cvd: bool,
+    // Which tower to open and how, from the USB flags.
+    usb: UsbOptions,
+    quiet: bool,
+    format: OutputFormat,
+    // How many times `-v` was given.
+    verbose: u8,
+}
+
+impl SendOptions {
+    /// Writes a `-v` trace line to stderr once `verbose` reaches `level`,
+    /// so stdout keeps to `ok` and JSON.
+    fn trace(&self, level: u8, message: fmt::Arguments) {
+        if self.verbose >= level {
+            eprintln!("usb: {message}");
+        }
+    }
+
+    /// Why a command that needs the tower itself cannot have it, under
+    /// `--simulate`, `--dry-run` or `--assume-device`.
+    fn no_tower(&self) -> Option<ControlError> {
+        if let Some(simulate) = self.simulate {
+            return Some(simulate.error(self).unwrap_or_else(|| {
+                ControlError::InvalidArg("--simulate has no tower to read from".into())
+            }));
+        }
+        // A dry run sets `assume_device` too, so name the flag actually given.
+        let flag = match (self.dry_run, self.assume_device) {
+            (true, _) => "--dry-run",
+            (false, true) => "--assume-device",
+            (false, false) => return None,
+        };
+        Some(ControlError::InvalidArg(format!(
+            "{flag} has no tower to read from"
+        )))
+    }
+}
+
 #[derive(Subcommand)]
 enum Command {
-    /// Set a single LED's state (color + pattern)
+    /// Set one or more LEDs to a shared state (colors + pattern)
+    #[command(allow_missing_positional = true)]
     Light {
-        #[arg(value_parser = parse_color, help = COLOR_HELP)]
-        color: u8,
+        #[arg(
+            value_parser = parse_color,
+            value_delimiter = ',',
+            num_args = 1,
+            required_unless_present_any = ["index", "name"],
+            conflicts_with_all = ["index", "name"],
+            value_name = "COLORS",
+            help = COLOR_LIST_HELP
+        )]
+        colors: Vec<u8>,
         #[arg(value_parser = parse_led_state, help = LED_HELP)]
         state: u8,
+        #[arg(long, value_parser = parse_color_index, value_name = "0-4", help = INDEX_HELP)]
+        index: Option<u8>,
+        #[arg(long, value_parser = parse_css_color, conflicts_with = "index", value_name = "CSS_COLOR", help = CSS_NAME_HELP)]
+        name: Option<u8>,
+        #[arg(long = "for", value_parser = parse_duration, value_name = "DURATION", help = FOR_HELP)]
+        hold: Option<Duration>,
     },
     /// Set the entire tower: red yellow green blue white
     Tower {
@@ -75,20 +355,105 @@ enum Command {
         blue: u8,
         #[arg(value_parser = parse_led_state, help = LED_HELP)]
         white: u8,
+        #[arg(long = "for", value_parser = parse_duration, value_name = "DURATION", help = FOR_HELP)]
+        hold: Option<Duration>,
+        #[arg(long, value_name = "MILLIS", help = STAGGER_HELP)]
+        stagger_ms: Option<u64>,
+    },
+    /// Set every lamp to one state
+    All {
+        #[arg(value_parser = parse_led_state, help = LED_HELP)]
+        state: u8,
+        #[arg(long, help = INCLUDE_WHITE_HELP)]
+        include_white: bool,
+        #[arg(long, conflicts_with = "include_white", help = EXCLUDE_WHITE_HELP)]
+        exclude_white: bool,
+        #[arg(long = "for", value_parser = parse_duration, value_name = "DURATION", help = FOR_HELP)]
+        hold: Option<Duration>,
+        #[arg(long, value_name = "MILLIS", help = STAGGER_HELP)]
+        stagger_ms: Option<u64>,
+    },
+    /// Light a lamp together with the buzzer pattern the config assigns to
+    /// its color under [alert_colors], in one report
+    AlertColor {
+        #[arg(value_parser = parse_color, help = COLOR_HELP)]
+        color: u8,
+    },
+    /// Show a monitoring check result: the whole tower in one report
+    Nagios {
+        #[arg(value_enum, ignore_case = true, help = NAGIOS_STATE_HELP)]
+        state: NagiosState,
+        #[arg(long, help = NO_BUZZER_HELP)]
+        no_buzzer: bool,
+    },
+    /// Accept the digit-string alert code of PATLITE's network towers, e.g.
+    /// `compat 100001` for red on with the buzzer, for migrating scripts
+    Compat {
+        #[arg(value_name = "DIGITS", help = COMPAT_CODE_HELP)]
+        code: String,
     },
     /// Control the buzzer with optional explicit pitches
     Buzzer {
-        #[arg(value_parser = parse_buzzer_pattern, help = BUZZER_HELP)]
-        pattern: u8,
-        #[arg(value_parser = parse_nibble, help = LIMIT_HELP)]
-        limit: u8,
+        #[arg(
+            value_parser = parse_buzzer_pattern,
+            required_unless_present = "intensity",
+            conflicts_with = "intensity",
+            help = BUZZER_HELP
+        )]
+        pattern: Option<u8>,
+        #[arg(
+            value_parser = parse_nibble,
+            required_unless_present = "intensity",
+            conflicts_with = "intensity",
+            help = LIMIT_HELP
+        )]
+        limit: Option<u8>,
         #[arg(value_parser = parse_nibble, help = PITCH_HELP)]
         pitch_a: Option<u8>,
         #[arg(value_parser = parse_nibble, help = PITCH_HELP)]
         pitch_b: Option<u8>,
+        #[arg(
+            long,
+            value_parser = parse_byte,
+            conflicts_with_all = ["pitch_a", "pitch_b", "pitch_a_only", "pitch_b_only"],
+            value_name = "BYTE",
+            help = PITCH_BYTE_HELP
+        )]
+        pitch_byte: Option<u8>,
+        #[arg(
+            long = "pitch-a",
+            value_parser = parse_nibble,
+            conflicts_with_all = ["pitch_a", "pitch_b"],
+            value_name = "NIBBLE",
+            help = PITCH_A_FLAG_HELP
+        )]
+        pitch_a_only: Option<u8>,
+        #[arg(
+            long = "pitch-b",
+            value_parser = parse_nibble,
+            conflicts_with_all = ["pitch_a", "pitch_b"],
+            value_name = "NIBBLE",
+            help = PITCH_B_FLAG_HELP
+        )]
+        pitch_b_only: Option<u8>,
+        #[arg(long, value_name = "MILLIS", help = COOLDOWN_HELP)]
+        cooldown_ms: Option<u64>,
+        #[arg(long = "for", value_parser = parse_duration, value_name = "DURATION", help = FOR_HELP)]
+        hold: Option<Duration>,
+        #[arg(long, conflicts_with = "hold", help = BLOCK_HELP)]
+        block: bool,
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=4), value_name = "1-4", help = INTENSITY_HELP)]
+        intensity: Option<u8>,
+        #[arg(long = "limit", value_parser = parse_nibble, requires = "intensity", conflicts_with_all = ["pattern", "limit"], value_name = "NIBBLE", help = LIMIT_FLAG_HELP)]
+        limit_only: Option<u8>,
     },
     /// Turn everything off
-    Reset,
+    Reset {
+        #[arg(long, help = KEEP_BUZZER_HELP)]
+        keep_buzzer: bool,
+        #[arg(short, long, help = YES_HELP)]
+        yes: bool,
+    },
     /// Send a raw 8-byte HID report (decimal or 0x-prefixed hex)
     Report {
         #[arg(
@@ -98,298 +463,4721 @@ enum Command {
             help = REPORT_HELP
         )]
         bytes: Vec<u8>,
+        #[arg(long, help = PROBE_SAFE_HELP)]
+        probe_safe: bool,
+    },
+    /// Show what an 8-byte report does, without touching USB
+    Decode {
+        #[arg(
+            value_parser = parse_byte,
+            num_args = REPORT_LEN,
+            value_name = "BYTE",
+            help = REPORT_HELP
+        )]
+        bytes: Vec<u8>,
+        #[arg(long, help = EXPLAIN_BITS_HELP)]
+        explain_bits: bool,
+    },
+    /// Read the tower and print its state (needs read-back)
+    Status {
+        #[arg(long, help = EXIT_CODE_HELP)]
+        exit_code: bool,
+    },
+    /// Set a single LED only if the tower is not already in that state
+    Ensure {
+        #[arg(value_parser = parse_color, help = COLOR_HELP)]
+        color: u8,
+        #[arg(value_parser = parse_led_state, help = LED_HELP)]
+        state: u8,
+    },
+    /// Read the tower repeatedly and print its state whenever it changes
+    Monitor {
+        #[arg(long, default_value_t = 500, value_name = "MILLIS", help = INTERVAL_HELP)]
+        interval_ms: u64,
+        #[arg(long, help = JSON_HELP)]
+        json: bool,
+    },
+    /// Run a shell command whenever the tower state changes (needs read-back)
+    OnChange {
+        #[arg(long, value_name = "COMMAND", help = EXEC_HELP)]
+        exec: String,
+        #[arg(long, default_value_t = 500, value_name = "MILLIS", help = INTERVAL_HELP)]
+        interval_ms: u64,
+    },
+    /// Watch a button bit in the tower's IN reports and run a command on
+    /// press and release (for models and firmware that report one)
+    PollButton {
+        #[arg(long, value_parser = parse_report_index, value_name = "INDEX", help = BUTTON_BYTE_HELP)]
+        byte: usize,
+        #[arg(long, value_parser = parse_byte, value_name = "MASK", help = BUTTON_MASK_HELP)]
+        mask: u8,
+        #[arg(long, value_name = "COMMAND", help = BUTTON_EXEC_HELP)]
+        exec: String,
+        #[arg(long, default_value_t = 50, value_name = "MILLIS", help = INTERVAL_HELP)]
+        interval_ms: u64,
+        #[arg(long, default_value_t = 30, value_name = "MILLIS", help = DEBOUNCE_HELP)]
+        debounce_ms: u64,
+    },
+    /// Fade a lamp in by stepping it from off through the patterns to solid
+    Pulse {
+        #[arg(value_parser = parse_color, help = COLOR_HELP)]
+        color: u8,
+        #[arg(long, value_enum, default_value_t = Easing::Linear, help = EASE_HELP)]
+        ease: Easing,
+        #[arg(long, default_value_t = 3000, value_name = "MILLIS", help = PULSE_DURATION_HELP)]
+        duration_ms: u64,
+        #[arg(long, help = PREVIEW_HELP)]
+        preview: bool,
+    },
+    /// Light each lamp between two colors in turn, one at a time, like a
+    /// gradient moving along the tower
+    Gradient {
+        #[arg(long, value_parser = parse_color, help = COLOR_HELP)]
+        from: u8,
+        #[arg(long, value_parser = parse_color, help = COLOR_HELP)]
+        to: u8,
+        #[arg(long, value_parser = parse_duration, default_value = "10s", value_name = "DURATION", help = GRADIENT_DURATION_HELP)]
+        duration: Duration,
+        #[arg(long = "loop", help = LOOP_HELP)]
+        repeat: bool,
+        #[arg(long, help = PREVIEW_HELP)]
+        preview: bool,
+    },
+    /// List attached towers with their USB location and identity strings
+    List {
+        #[arg(long, help = LIST_JSON_HELP)]
+        json: bool,
+    },
+    /// Print every color, LED state and buzzer alias with its value
+    Aliases {
+        #[arg(long, help = ALIASES_JSON_HELP)]
+        json: bool,
+    },
+    /// Print every combination of defined lamp and buzzer states with its
+    /// report, for generating documentation tables and exhaustive tests
+    States {
+        #[arg(long, help = STATES_JSON_HELP)]
+        json: bool,
+    },
+    /// Print this build's version, supported devices, USB defaults and
+    /// platform features, for bug reports
+    Info {
+        #[arg(long, help = INFO_JSON_HELP)]
+        json: bool,
+    },
+    /// Write the tower's current state to a file as a report line
+    Save {
+        #[arg(value_name = "FILE")]
+        path: PathBuf,
     },
+    /// Send the report saved in a file by `save`
+    #[command(visible_alias = "load")]
+    Restore {
+        #[arg(value_name = "FILE")]
+        path: PathBuf,
+    },
+    /// Blink a lamp briefly every interval to show the host is alive, riding
+    /// out USB errors and reconnects (meant to run as a supervised service)
+    Heartbeat {
+        #[arg(long, value_parser = parse_color, default_value = "green", help = COLOR_HELP)]
+        color: u8,
+        #[arg(long, default_value_t = 1000, value_name = "MILLIS", help = HEARTBEAT_INTERVAL_HELP)]
+        interval_ms: u64,
+        #[arg(long, default_value_t = 100, value_name = "MILLIS", help = HEARTBEAT_ON_HELP)]
+        on_ms: u64,
+    },
+    /// Blink a lamp in software at any rate over one claimed interface,
+    /// leaving it off at the end or on Ctrl-C
+    Blink {
+        #[arg(value_parser = parse_color, help = COLOR_HELP)]
+        color: u8,
+        #[arg(long, default_value_t = 500, value_name = "MILLIS", help = BLINK_PERIOD_HELP)]
+        period_ms: u64,
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..), help = BLINK_COUNT_HELP)]
+        count: u32,
+    },
+    /// Play notes on the buzzer over one claimed interface, silencing it
+    /// at the end or on Ctrl-C
+    Melody {
+        #[arg(value_parser = parse_note, value_delimiter = ',', required = true, value_name = "PITCH:MILLIS", help = MELODY_HELP)]
+        notes: Vec<Note>,
+    },
+    /// Write a base report over and over with one byte stepped through a
+    /// range, printing each value, to work out what the byte does
+    Sweep {
+        #[arg(long, value_parser = parse_report_index, value_name = "INDEX", help = SWEEP_BYTE_HELP)]
+        byte: usize,
+        #[arg(long, value_parser = parse_byte, default_value = "0", value_name = "BYTE", help = SWEEP_FROM_HELP)]
+        from: u8,
+        #[arg(long, value_parser = parse_byte, default_value = "255", value_name = "BYTE", help = SWEEP_TO_HELP)]
+        to: u8,
+        #[arg(long, default_value_t = 300, value_name = "MILLIS", help = SWEEP_STEP_HELP)]
+        step_ms: u64,
+        #[arg(long, value_parser = parse_byte, num_args = REPORT_LEN, value_name = "BYTE", help = SWEEP_BASE_HELP)]
+        base: Option<Vec<u8>>,
+        #[arg(long, help = RESET_TO_BASE_HELP)]
+        reset_to_base: bool,
+    },
+    /// Write alternating reset and light reports over one claimed interface
+    /// and report the sustained rate and write latency
+    Bench {
+        #[arg(long, value_parser = parse_duration, default_value = "5s", value_name = "DURATION", help = BENCH_DURATION_HELP)]
+        duration: Duration,
+    },
+    /// Write one report per JSON line read from stdin, e.g.
+    /// {"red":"on","green":"off","buzzer":"pattern1","limit":2}; omitted
+    /// lamps and buzzer are kept
+    Stream {
+        #[arg(long, default_value_t = 50, value_name = "PER_SECOND", help = MAX_RATE_HELP)]
+        max_rate: u32,
+    },
+    /// Open the tower, say whether it is attached locally or over USB/IP,
+    /// and time the open and a no-op write
+    RemoteInfo,
+    /// Run subcommand lines from a file over one claimed interface
+    RunFile {
+        #[arg(value_name = "FILE")]
+        path: PathBuf,
+        #[arg(long, help = CONTINUE_ON_ERROR_HELP)]
+        continue_on_error: bool,
+    },
+    /// Run subcommand lines read from stdin over one claimed interface, e.g.
+    /// from a pipe or heredoc; blank lines and `#` comments are skipped
+    Batch {
+        #[arg(long, help = CONTINUE_ON_ERROR_HELP)]
+        continue_on_error: bool,
+    },
+    /// Hold the device open and run subcommand lines typed at a prompt, with
+    /// line editing and history for the session
+    Repl {
+        #[arg(long, help = RESET_ON_EXIT_HELP)]
+        reset_on_exit: bool,
+    },
+    /// Hold the device open and run subcommand lines sent over a Unix socket,
+    /// answering each with `ok` (or the usual status line) or `error: ...`
+    #[cfg(unix)]
+    Socket {
+        #[arg(long, value_name = "PATH", help = SOCKET_PATH_HELP)]
+        path: PathBuf,
+    },
+    /// Hold the device open and run subcommand lines written to a named
+    /// pipe, reopening it whenever the last writer closes it
+    #[cfg(unix)]
+    Fifo {
+        #[arg(long, value_name = "PATH", help = FIFO_PATH_HELP)]
+        path: PathBuf,
+    },
+    /// Print a systemd unit that runs a long-lived subcommand as a service
+    #[cfg(target_os = "linux")]
+    InstallService {
+        #[arg(long, value_enum, default_value_t = ServiceMode::Heartbeat, help = SERVICE_MODE_HELP)]
+        mode: ServiceMode,
+        #[arg(long, value_name = "FILE", help = SERVICE_OUTPUT_HELP)]
+        output: Option<PathBuf>,
+        #[arg(last = true, value_name = "ARGS", help = SERVICE_ARGS_HELP)]
+        args: Vec<String>,
+    },
+    /// Show a scene from the `[scenes]` config table; lamps it does not name
+    /// are off, as is the buzzer unless it sets `buzzer`
+    Scene {
+        #[arg(value_name = "NAME")]
+        name: String,
+        #[arg(long, help = MINIMAL_HELP)]
+        minimal: bool,
+    },
+    /// Choose each lamp's state and the buzzer at prompts and save them as a
+    /// `[scenes.<NAME>]` table in the config file, replacing any of that name
+    SceneEdit {
+        #[arg(value_parser = parse_scene_name, value_name = "NAME")]
+        name: String,
+    },
+    /// Parse the config files and check every setting without touching USB,
+    /// listing each problem found
+    CheckConfig,
+    /// Print a shell completion script (e.g. `source <(ptltectl completions bash)`)
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Run worker processes that each open, claim and write to the tower over
+    /// and over, and report how often claiming had to wait for another
+    #[command(hide = true)]
+    Stress {
+        #[arg(long, default_value_t = 4, value_name = "N")]
+        processes: usize,
+        #[arg(long, default_value_t = 20, value_name = "M")]
+        iterations: usize,
+    },
+    /// One `stress` worker; prints `writes retried busy_retries failures`
+    #[command(name = "__stress-worker", hide = true)]
+    StressWorker {
+        #[arg(long)]
+        iterations: usize,
+    },
+    /// Scene names from the config, one per line, for the completion script
+    #[command(name = "__complete-scenes", hide = true)]
+    CompleteScenes,
 }
 
-fn main() {
-    let cli = Cli::parse();
-    match run(cli) {
-        Ok(()) => println!("ok"),
-        Err(err) => {
-            eprintln!("Error: {err}");
-            std::process::exit(1);
-        }
-    }
+#[derive(Clone, Copy, ValueEnum)]
+enum NagiosState {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
 }
 
-fn run(cli: Cli) -> ControlResult<()> {
-    match cli.command {
-        Command::Light { color, state } => {
-            let (led_ry, led_gb, led_w) = assemble_leds(color, state)?;
-            send_report(build_report(BUZZER_KEEP, 0, led_ry, led_gb, led_w))
-        }
-        Command::Tower {
-            red,
-            yellow,
-            green,
-            blue,
-            white,
-        } => {
-            let led_ry = (nibble(red) << 4) | nibble(yellow);
-            let led_gb = (nibble(green) << 4) | nibble(blue);
-            let led_w = nibble(white) << 4;
-            send_report(build_report(BUZZER_KEEP, 0, led_ry, led_gb, led_w))
+impl NagiosState {
+    fn name(self) -> &'static str {
+        match self {
+            NagiosState::Ok => "ok",
+            NagiosState::Warning => "warning",
+            NagiosState::Critical => "critical",
+            NagiosState::Unknown => "unknown",
         }
-        Command::Buzzer {
-            pattern,
-            limit,
-            pitch_a,
-            pitch_b,
-        } => {
-            let (pitch_a, pitch_b) = match (pitch_a, pitch_b) {
-                (Some(a), Some(b)) => (a, b),
-                (None, None) => (BUZZER_PITCH_DEFAULT_A, BUZZER_PITCH_DEFAULT_B),
-                _ => {
-                    return Err(ControlError::InvalidArg(
-                        "pitch values must include both A and B nibbles".into(),
-                    ));
-                }
-            };
+    }
 
-            let buzzer = (nibble(limit) << 4) | nibble(pattern);
-            let pitch = (nibble(pitch_a) << 4) | nibble(pitch_b);
-            send_report(build_report(
-                buzzer,
-                pitch,
-                LED_KEEP_PAIR,
-                LED_KEEP_PAIR,
-                LED_KEEP_HIGH,
-            ))
-        }
-        Command::Reset => send_report(build_report(
-            BUZZER_OFF, PITCH_OFF, LED_OFF, LED_OFF, LED_OFF,
-        )),
-        Command::Report { bytes } => {
-            if bytes.len() != REPORT_LEN {
-                return Err(ControlError::InvalidArg("report must be 8 bytes".into()));
+    /// Lamps (red to white) and buzzer pattern shown by default.
+    fn default_scene(self) -> ([u8; COLOR_COUNT], u8) {
+        let mut leds = [LED_OFF; COLOR_COUNT];
+        let mut buzzer = BUZZER_OFF;
+        match self {
+            NagiosState::Ok => leds[usize::from(COLOR_GREEN)] = LED_ON,
+            NagiosState::Warning => leds[usize::from(COLOR_YELLOW)] = LED_ON,
+            NagiosState::Critical => {
+                leds[usize::from(COLOR_RED)] = LED_ON;
+                buzzer = BUZZER_ON;
             }
-            let mut report = [0u8; REPORT_LEN];
-            report.copy_from_slice(&bytes);
-            send_report(report)
+            NagiosState::Unknown => leds[usize::from(COLOR_BLUE)] = 0x2,
         }
+        (leds, buzzer)
     }
 }
 
-fn send_report(report: [u8; REPORT_LEN]) -> ControlResult<()> {
-    let context = rusb::Context::new()?;
-    let mut handle = open_device(&context)?;
-
-    let _ = handle.set_auto_detach_kernel_driver(true);
-    claim_interface_with_retry(&mut handle, 0)?;
+#[derive(Clone, Copy, ValueEnum)]
+enum Simulate {
+    /// Every write succeeds
+    Ok,
+    /// No tower is attached
+    NotFound,
+    /// Another process holds the interface
+    Busy,
+    /// The user may not open the device
+    Access,
+    /// The tower was unplugged after it was opened
+    Unplugged,
+}
 
-    let timeout = Duration::from_millis(TIMEOUT_MS);
-    let written = handle.write_interrupt(ENDPOINT_OUT, &report, timeout)?;
-    if written != REPORT_LEN {
-        return Err(ControlError::ShortWrite);
+impl Simulate {
+    /// The error a real tower in this situation produces, `None` for `ok`.
+    fn error(self, options: &SendOptions) -> Option<ControlError> {
+        match self {
+            Simulate::Ok => None,
+            Simulate::NotFound => Some(ControlError::DeviceNotFound(
+                options.usb.vendor_id,
+                options.usb.product_ids.clone(),
+            )),
+            Simulate::Busy => Some(usb_error("claim")(rusb::Error::Busy)),
+            Simulate::Access => Some(ControlError::DeviceOpenFailed(rusb::Error::Access)),
+            Simulate::Unplugged => Some(ControlError::DeviceDisconnected),
+        }
     }
+}
 
-    Ok(())
+#[derive(Clone, Copy, ValueEnum)]
+enum ServiceMode {
+    /// Blink a lamp to show the host is alive
+    Heartbeat,
+    /// Log every state change of the tower
+    Monitor,
 }
 
-fn open_device<T: UsbContext>(context: &T) -> ControlResult<rusb::DeviceHandle<T>> {
-    let devices = context.devices()?;
+impl ServiceMode {
+    fn subcommand(self) -> &'static str {
+        match self {
+            ServiceMode::Heartbeat => "heartbeat",
+            ServiceMode::Monitor => "monitor",
+        }
+    }
+}
 
-    let mut saw_matching_vid_pid = false;
-    let mut last_open_error: Option<rusb::Error> = None;
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum TransferArg {
+    /// Write to the interrupt OUT endpoint (LR6-USB)
+    Interrupt,
+    /// Send a control transfer, e.g. a HID SET_REPORT
+    Control,
+}
 
-    for device in devices.iter() {
-        let descriptor = match device.device_descriptor() {
-            Ok(descriptor) => descriptor,
-            Err(_) => continue,
-        };
+fn parse_control_setup(value: &str) -> Result<ControlSetup, String> {
+    let fields: Vec<_> = value.split(',').map(str::trim).collect();
+    let [request_type, request, setup_value, index] = fields.as_slice() else {
+        return Err(format!(
+            "expected REQUEST_TYPE,REQUEST,VALUE,INDEX, found '{value}'"
+        ));
+    };
+    Ok(ControlSetup {
+        request_type: parse_u8_any(request_type)?,
+        request: parse_u8_any(request)?,
+        value: parse_u16_any(setup_value)?,
+        index: parse_u16_any(index)?,
+    })
+}
 
-        if descriptor.vendor_id() != VENDOR_ID || descriptor.product_id() != PRODUCT_ID {
-            continue;
-        }
+#[derive(Clone, Copy, ValueEnum)]
+enum ErrorFormat {
+    /// `Error: <message>`
+    Plain,
+    /// Colored message plus a hint line where one applies
+    Human,
+    /// {"status":"error","message":"<message>","hint":"<hint>"|null}
+    Json,
+}
 
-        saw_matching_vid_pid = true;
-        match device.open() {
-            Ok(handle) => return Ok(handle),
-            Err(err) => last_open_error = Some(err),
+impl ErrorFormat {
+    fn print(self, err: &ControlError) {
+        match self {
+            ErrorFormat::Plain => eprintln!("Error: {err}"),
+            ErrorFormat::Human => {
+                eprintln!("\x1b[1;31merror:\x1b[0m {err}");
+                if let Some(hint) = err.hint() {
+                    eprintln!("\x1b[1;36mhint:\x1b[0m {hint}");
+                }
+            }
+            ErrorFormat::Json => eprintln!("{}", error_json(err)),
         }
     }
-
-    if saw_matching_vid_pid {
-        Err(ControlError::DeviceOpenFailed(
-            last_open_error.unwrap_or(rusb::Error::Other),
-        ))
-    } else {
-        Err(ControlError::DeviceNotFound)
-    }
 }
 
-fn assemble_leds(color: u8, state: u8) -> ControlResult<(u8, u8, u8)> {
-    let state = nibble(state);
-    let keep = LED_KEEP_PAIR;
-    match color {
-        COLOR_RED => Ok(((state << 4) | LED_KEEP, keep, LED_KEEP_HIGH)),
-        COLOR_YELLOW => Ok(((LED_KEEP << 4) | state, keep, LED_KEEP_HIGH)),
-        COLOR_GREEN => Ok((keep, (state << 4) | LED_KEEP, LED_KEEP_HIGH)),
-        COLOR_BLUE => Ok((keep, (LED_KEEP << 4) | state, LED_KEEP_HIGH)),
-        COLOR_WHITE => Ok((keep, keep, state << 4)),
-        _ => Err(ControlError::InvalidArg("color out of range".into())),
-    }
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// `ok`, `unchanged` and friends; text listings
+    Text,
+    /// {"status":"ok","report":[...]} and the like; JSON listings
+    Json,
 }
 
-fn build_report(buzzer: u8, pitch: u8, led_ry: u8, led_gb: u8, led_w: u8) -> [u8; REPORT_LEN] {
-    [
-        COMMAND_VERSION,
-        COMMAND_ID,
-        buzzer,
-        pitch,
-        led_ry,
-        led_gb,
-        led_w,
-        0,
-    ]
+#[derive(Clone, Copy, ValueEnum)]
+enum CodeLanguage {
+    Rust,
+    C,
 }
 
-fn claim_interface_with_retry<T: UsbContext>(
-    handle: &mut rusb::DeviceHandle<T>,
-    interface: u8,
-) -> ControlResult<()> {
-    let strategy = Fixed::from_millis(BUSY_RETRY_DELAY_MS).take(BUSY_RETRY_ATTEMPTS);
-    retry(strategy, || match handle.claim_interface(interface) {
-        Ok(()) => OperationResult::Ok(()),
-        Err(rusb::Error::Busy) => OperationResult::Retry(rusb::Error::Busy),
-        Err(err) => OperationResult::Err(err),
-    })
-    .map_err(|err| ControlError::from(err.error))
+impl CodeLanguage {
+    fn literal(self, report: &[u8; REPORT_LEN]) -> String {
+        let bytes: Vec<_> = report.iter().map(|byte| format!("{byte:#04x}")).collect();
+        match self {
+            CodeLanguage::Rust => {
+                format!("let report: [u8; {REPORT_LEN}] = [{}];", bytes.join(", "))
+            }
+            CodeLanguage::C => format!("uint8_t report[{REPORT_LEN}] = {{{}}};", bytes.join(", ")),
+        }
+    }
 }
 
-fn nibble(value: u8) -> u8 {
-    value & 0x0F
+#[derive(Clone, Copy, ValueEnum)]
+enum Shell {
+    Bash,
 }
 
-#[derive(Debug)]
-enum ControlError {
-    DeviceNotFound,
-    DeviceOpenFailed(rusb::Error),
-    InvalidArg(String),
-    Usb(rusb::Error),
-    ShortWrite,
+#[derive(Clone, Copy, ValueEnum)]
+enum Easing {
+    /// Equal dwell on every step
+    Linear,
+    /// Linger on the dim steps, then speed up
+    EaseIn,
+    /// Move quickly off the dim steps, then linger near solid
+    EaseOut,
 }
 
-impl fmt::Display for ControlError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Easing {
+    /// Fraction of the total time at which fraction `progress` of the steps
+    /// has been reached (the inverse of the usual quadratic easing curves).
+    fn time_at(self, progress: f64) -> f64 {
         match self {
-            ControlError::DeviceNotFound => write!(f, "device 191a:8003 not found"),
-            ControlError::DeviceOpenFailed(rusb::Error::Access) => write!(
-                f,
-                "permission denied opening device 191a:8003 (try `sudo`, or add a udev rule to grant access)"
-            ),
-            ControlError::DeviceOpenFailed(err) => {
-                write!(f, "failed to open device 191a:8003: {err}")
-            }
-            ControlError::InvalidArg(msg) => write!(f, "{msg}"),
-            ControlError::Usb(rusb::Error::Access) => write!(
-                f,
-                "permission denied talking to device 191a:8003 (try `sudo`, or add a udev rule to grant access)"
-            ),
-            ControlError::Usb(err) => write!(f, "usb error: {err}"),
-            ControlError::ShortWrite => write!(f, "usb short write"),
+            Easing::Linear => progress,
+            Easing::EaseIn => progress.sqrt(),
+            Easing::EaseOut => 1.0 - (1.0 - progress).sqrt(),
         }
     }
 }
 
-impl From<rusb::Error> for ControlError {
-    fn from(err: rusb::Error) -> Self {
-        ControlError::Usb(err)
-    }
+/// One line of a script or prompt, parsed with the same grammar as the CLI.
+#[derive(Parser)]
+#[command(no_binary_name = true, disable_version_flag = true)]
+struct ScriptLine {
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn parse_color(value: &str) -> Result<u8, String> {
-    if let Some(alias) = color_alias(value) {
-        return Ok(alias);
-    }
+enum Outcome {
+    Sent,
+    Unchanged,
+    Silent,
+    Validated([u8; REPORT_LEN]),
+    DryRun([u8; REPORT_LEN]),
+    CoolingDown(Duration),
+    Delivered(SentInfo),
+    Exit(u8),
+    /// What a read command exists to produce, with its exit status, so a
+    /// socket client gets it in the reply line.
+    Output(String, u8),
+}
 
-    let num = parse_u8_any(value).map_err(|_| format!("unknown color '{value}'"))?;
-    if num <= COLOR_WHITE {
-        Ok(num)
-    } else {
-        Err(format!(
-            "color index {num} out of range (0-{})",
-            COLOR_WHITE
-        ))
+fn main() {
+    // Color arguments are resolved while the command line is parsed, so the
+    // alias file has to be loaded first.
+    if let Some(path) = color_alias_file_arg() {
+        match load_color_aliases(&path) {
+            Ok(aliases) => {
+                let _ = SITE_COLOR_ALIASES.set(aliases);
+            }
+            Err(err) => {
+                eprintln!("Error: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+    let cli = Cli::parse();
+    let error_format = cli.error_format;
+    let format = cli.format;
+    if let Err(err) = run(cli) {
+        match format {
+            OutputFormat::Json => println!("{}", error_json(&err)),
+            OutputFormat::Text => error_format.print(&err),
+        }
+        std::process::exit(err.exit_code());
     }
 }
 
-fn color_alias(value: &str) -> Option<u8> {
-    let lower = value.to_ascii_lowercase();
-    match lower.as_str() {
-        "red" => Some(COLOR_RED),
-        "yellow" => Some(COLOR_YELLOW),
-        "green" => Some(COLOR_GREEN),
-        "blue" => Some(COLOR_BLUE),
-        "white" => Some(COLOR_WHITE),
-        _ => None,
+/// The status line for a finished command. `--quiet` drops it entirely,
+/// but not the output a read command exists to produce.
+fn print_outcome(outcome: &Outcome, options: &SendOptions) {
+    if let Outcome::Output(text, _) = outcome {
+        println!("{text}");
+        return;
+    }
+    if options.quiet {
+        return;
+    }
+    if let Some(line) = outcome_line(outcome, options) {
+        println!("{line}");
     }
 }
 
-fn parse_led_state(value: &str) -> Result<u8, String> {
-    if let Some(alias) = led_state_alias(value) {
-        return Ok(alias);
+fn outcome_line(outcome: &Outcome, options: &SendOptions) -> Option<String> {
+    if let Some(template) = &options.report_template
+        && let Outcome::Validated(report)
+        | Outcome::DryRun(report)
+        | Outcome::Delivered(SentInfo { report, .. }) = outcome
+    {
+        return Some(fill_template(template, report).unwrap_or_default());
+    }
+    if let Outcome::Output(text, _) = outcome {
+        return Some(text.clone());
+    }
+    if options.format == OutputFormat::Json {
+        return outcome_json(outcome);
     }
-    parse_nibble(value)
+    Some(match outcome {
+        Outcome::Sent => "ok".into(),
+        Outcome::Delivered(info) if options.verbose > 0 => format!("ok {info}"),
+        Outcome::Delivered(_) => "ok".into(),
+        Outcome::Unchanged => "unchanged".into(),
+        Outcome::Silent | Outcome::Exit(_) | Outcome::Output(..) => return None,
+        Outcome::Validated(report) => format!("{} valid", format_report(report)),
+        Outcome::DryRun(report) => format_report(report),
+        Outcome::CoolingDown(remaining) => {
+            format!("cooling down: buzzer silent for another {remaining:.0?}")
+        }
+    })
 }
 
-fn led_state_alias(value: &str) -> Option<u8> {
-    let lower = value.to_ascii_lowercase();
-    match lower.as_str() {
-        "led_off" | "off" => Some(0x0),
-        "led_on" | "on" | "solid" => Some(0x1),
-        "led_pattern1" | "pattern1" => Some(0x2),
-        "led_pattern2" | "pattern2" => Some(0x3),
-        "led_pattern3" | "pattern3" => Some(0x4),
-        "led_pattern4" | "pattern4" => Some(0x5),
-        "led_keep" | "keep" => Some(LED_KEEP),
-        _ => None,
-    }
+/// The JSON object for a failed command, the same on stdout under
+/// `--format json` as on stderr under `--error-format json`.
+fn error_json(err: &ControlError) -> String {
+    format!(
+        "{{\"status\":\"error\",\"message\":{},\"hint\":{}}}",
+        json_string(&err.to_string()),
+        err.hint().map_or_else(|| "null".into(), json_string)
+    )
 }
 
-fn parse_buzzer_pattern(value: &str) -> Result<u8, String> {
-    if let Some(alias) = buzzer_alias(value) {
-        return Ok(alias);
-    }
-    parse_nibble(value)
+/// `outcome_line` under `--format json`: the status, plus the bytes written
+/// when they are known.
+fn outcome_json(outcome: &Outcome) -> Option<String> {
+    let (status, report) = match outcome {
+        Outcome::Sent => ("ok", None),
+        Outcome::Delivered(SentInfo { report, .. })
+        | Outcome::Validated(report)
+        | Outcome::DryRun(report) => ("ok", Some(report)),
+        Outcome::Unchanged => ("unchanged", None),
+        Outcome::CoolingDown(remaining) => {
+            return Some(format!(
+                "{{\"status\":\"cooling_down\",\"remaining_ms\":{}}}",
+                remaining.as_millis()
+            ));
+        }
+        Outcome::Silent | Outcome::Exit(_) | Outcome::Output(..) => return None,
+    };
+    Some(match report {
+        Some(report) => {
+            let bytes: Vec<_> = report.iter().map(u8::to_string).collect();
+            format!(
+                "{{\"status\":\"{status}\",\"report\":[{}]}}",
+                bytes.join(",")
+            )
+        }
+        None => format!("{{\"status\":\"{status}\"}}"),
+    })
 }
 
-fn buzzer_alias(value: &str) -> Option<u8> {
-    let lower = value.to_ascii_lowercase();
-    match lower.as_str() {
-        "buzz_off" | "buzzer_off" | "off" => Some(0x0),
-        "buzz_on" | "buzzer_on" | "on" => Some(0x1),
-        "buzz_pattern1" | "pattern1" => Some(0x2),
-        "buzz_pattern2" | "pattern2" => Some(0x3),
-        "buzz_pattern3" | "pattern3" => Some(0x4),
-        "buzz_pattern4" | "pattern4" => Some(0x5),
-        "buzzer_keep" | "keep" => Some(LED_KEEP),
-        _ => None,
+fn run(cli: Cli) -> ControlResult<()> {
+    if let Some(path) = &cli.if_present
+        && !sentinel_matches(path, cli.if_content.as_deref())
+    {
+        return Ok(());
+    }
+    if let Some(template) = &cli.report_template {
+        fill_template(template, &reset_report(false))
+            .map_err(|err| ControlError::InvalidArg(format!("--report-template: {err}")))?;
+    }
+    if !cli.detach_interfaces.contains(&0) {
+        return Err(ControlError::InvalidArg(
+            "--detach-interfaces must include interface 0, which carries the reports".into(),
+        ));
     }
+    // libusb keeps timeouts as an unsigned int of milliseconds.
+    if cli
+        .timeout_ms
+        .is_some_and(|millis| millis > u64::from(u32::MAX))
+    {
+        return Err(ControlError::InvalidArg(format!(
+            "--timeout must be at most {} milliseconds",
+            u32::MAX
+        )));
+    }
+    if cli.serial.is_some() && cli.bus.is_some() {
+        return Err(ControlError::InvalidArg(
+            "--serial and --bus/--address both select a tower; give only one".into(),
+        ));
+    }
+    let config = Config::load(&cli.config).map_err(ControlError::Config)?;
+    let strict = cli.strict
+        || config
+            .flag("require_selection_when_ambiguous")
+            .map_err(ControlError::Config)?;
+    // `--timeout` when given, else the default for the connection, which
+    // `--remote` stretches for USB/IP. libusb takes a zero duration as no
+    // timeout at all.
+    let timeout_ms = match cli.timeout_ms {
+        Some(millis) => millis,
+        None if cli.remote => REMOTE_TIMEOUT_MS,
+        None => TIMEOUT_MS,
+    };
+    let usb = UsbOptions {
+        vendor_id: cli.vendor_id,
+        product_ids: match cli.product_id {
+            Some(product_id) => vec![product_id],
+            None => SUPPORTED_PRODUCTS.to_vec(),
+        },
+        serial: cli.serial,
+        location: cli.bus.zip(cli.address),
+        strict,
+        usb_reset: cli.usb_reset,
+        auto_detach: !cli.no_auto_detach,
+        interfaces: cli.detach_interfaces,
+        claim_retries: cli.claim_retries,
+        claim_retry_delay: Duration::from_millis(cli.claim_retry_delay_ms),
+        timeout: Duration::from_millis(timeout_ms),
+        transfer: match cli.transfer {
+            TransferArg::Interrupt => Transfer::Interrupt,
+            TransferArg::Control => Transfer::Control(cli.control_setup.unwrap_or(HID_SET_REPORT)),
+        },
+        min_firmware: cli.min_firmware,
+    };
+    let options = SendOptions {
+        report_file: cli.report_file,
+        report_template: cli.report_template,
+        // A dry run is an assumed device that skips validation.
+        assume_device: cli.assume_device || cli.dry_run,
+        dry_run: cli.dry_run,
+        simulate: cli.simulate,
+        timing: cli.timing,
+        accept_short: cli.accept_short,
+        hidraw: cli.hidraw,
+        report_id: cli.report_id,
+        retry_for: cli.retry_for,
+        wait_for_device: cli.wait_for_device,
+        swap_nibbles: cli.swap_nibbles,
+        merge_cached: cli.merge_cached,
+        mirror: cli.mirror && io::stdout().is_terminal(),
+        explain: cli.explain,
+        emit_code: cli.emit_code,
+        emit_shell: cli.emit_shell,
+        cvd: cli.cvd,
+        usb,
+        quiet: cli.quiet,
+        format: cli.format,
+        verbose: cli.verbose,
+    };
+    let via_daemon =
+        cli.via_daemon || config.flag("prefer_daemon").map_err(ControlError::Config)?;
+    let routed = match via_daemon {
+        true => daemon::forward(&cli.command, &options, &config)?,
+        false => None,
+    };
+    let outcome = match routed {
+        Some(outcome) => outcome,
+        None => {
+            let mut connector = Connector::new(&options);
+            execute(
+                cli.command,
+                &mut Session::new(&options, &config, &mut connector),
+            )?
+        }
+    };
+    print_outcome(&outcome, &options);
+    match outcome {
+        Outcome::Exit(code) | Outcome::Output(_, code @ 1..) => std::process::exit(code.into()),
+        _ => {}
+    }
+    Ok(())
 }
 
-fn parse_nibble(value: &str) -> Result<u8, String> {
-    let num = parse_u8_any(value).map_err(|_| format!("invalid nibble '{value}'"))?;
-    if num <= 0x0F {
-        Ok(num)
-    } else {
-        Err(format!("nibble {num:#x} out of range (0x0-0xF)"))
+/// A missing or unreadable sentinel means the condition is not met.
+fn sentinel_matches(path: &Path, content: Option<&str>) -> bool {
+    match content {
+        None => path.exists(),
+        Some(expected) => fs::read_to_string(path).is_ok_and(|text| text.trim() == expected),
     }
 }
 
-fn parse_byte(value: &str) -> Result<u8, String> {
-    parse_u8_any(value).map_err(|_| format!("invalid byte '{value}'"))
-}
+fn execute(command: Command, session: &mut Session) -> ControlResult<Outcome> {
+    match command {
+        Command::Light {
+            colors,
+            state,
+            index,
+            name,
+            hold,
+        } => {
+            let colors = index.or(name).map_or(colors, |color| vec![color]);
+            let report = session.cvd(light_report(&colors, state)?)?;
+            send_for(session, report, hold)
+        }
+        Command::Tower {
+            red,
+            yellow,
+            green,
+            blue,
+            white,
+            hold,
+            stagger_ms,
+        } => {
+            let report = session.cvd(tower_report([red, yellow, green, blue, white])?)?;
+            send_lamps(session, report, stagger_ms, hold)
+        }
+        Command::All {
+            state,
+            include_white: _,
+            exclude_white,
+            hold,
+            stagger_ms,
+        } => {
+            let colors: &[u8] = if exclude_white {
+                &[COLOR_RED, COLOR_YELLOW, COLOR_GREEN, COLOR_BLUE]
+            } else {
+                &[
+                    COLOR_RED,
+                    COLOR_YELLOW,
+                    COLOR_GREEN,
+                    COLOR_BLUE,
+                    COLOR_WHITE,
+                ]
+            };
+            let report = session.cvd(light_report(colors, state)?)?;
+            send_lamps(session, report, stagger_ms, hold)
+        }
+        Command::AlertColor { color } => {
+            let report = alert_color_report(session.config, color)?;
+            session.send(session.cvd(report)?)
+        }
+        Command::Nagios { state, no_buzzer } => {
+            let report = nagios_report(session.config, state, no_buzzer)?;
+            session.send(session.cvd(report)?)
+        }
+        Command::Compat { code } => {
+            let report = compat_report(&code).map_err(ControlError::InvalidArg)?;
+            session.send(session.cvd(report)?)
+        }
+        Command::Buzzer {
+            pattern,
+            limit,
+            pitch_a,
+            pitch_b,
+            pitch_byte,
+            pitch_a_only,
+            pitch_b_only,
+            cooldown_ms,
+            hold,
+            block,
+            intensity,
+            limit_only,
+        } => {
+            let (pattern, limit) = match intensity {
+                Some(level) => (
+                    buzzer_intensity_pattern(session.config, level)?,
+                    limit_only.unwrap_or(0),
+                ),
+                None => (pattern.unwrap_or(BUZZER_KEEP), limit.unwrap_or(0)),
+            };
+            if block && limit == 0 {
+                return Err(ControlError::InvalidArg(
+                    "--block needs a timed limit (1-15); limit 0 sounds until reset".into(),
+                ));
+            }
+            let (pitch_a, pitch_b) = match (pitch_byte, pitch_a, pitch_b) {
+                (Some(byte), _, _) => (byte >> 4, nibble(byte)),
+                (None, Some(a), Some(b)) => (a, b),
+                (None, None, None) => (
+                    pitch_a_only.unwrap_or(BUZZER_PITCH_DEFAULT_A),
+                    pitch_b_only.unwrap_or(BUZZER_PITCH_DEFAULT_B),
+                ),
+                _ => {
+                    return Err(ControlError::InvalidArg(
+                        "pitch values must include both A and B nibbles".into(),
+                    ));
+                }
+            };
 
-fn parse_u8_any(value: &str) -> Result<u8, String> {
-    if let Some(hex) = value
-        .strip_prefix("0x")
-        .or_else(|| value.strip_prefix("0X"))
-    {
-        u8::from_str_radix(hex, 16).map_err(|_| format!("invalid hex value '{value}'"))
-    } else {
-        value
-            .parse::<u8>()
-            .map_err(|_| format!("invalid number '{value}'"))
+            let buzzer = (nibble(limit) << 4) | nibble(pattern);
+            let pitch = (nibble(pitch_a) << 4) | nibble(pitch_b);
+            let report = build_report(buzzer, pitch, LED_KEEP_PAIR, LED_KEEP_PAIR, LED_KEEP_HIGH);
+
+            // Silencing is never held back, and without a tower (a dry run,
+            // an assumed or a simulated one) nothing sounds.
+            let sounds = pattern != BUZZER_OFF && pattern != BUZZER_KEEP;
+            let outcome = match cooldown_ms {
+                Some(cooldown) if sounds && session.options.no_tower().is_none() => {
+                    let stamp = state_path("buzzer", &session.device_serial()?)?;
+                    let cooldown = Duration::from_millis(cooldown);
+                    if let Some(elapsed) = since_last_buzz(&stamp)
+                        && elapsed < cooldown
+                    {
+                        return Ok(Outcome::CoolingDown(cooldown - elapsed));
+                    }
+                    let outcome = session.send(report)?;
+                    record_buzz(&stamp)?;
+                    clear_after(session, report, hold, outcome)
+                }
+                _ => send_for(session, report, hold),
+            }?;
+            match buzzer_limit_duration(limit) {
+                Some(duration) if block && sounds && matches!(outcome, Outcome::Delivered(_)) => {
+                    if interrupt::sleep(duration) {
+                        session.send(clear_report(&report))?;
+                        return Err(ControlError::Interrupted);
+                    }
+                    Ok(outcome)
+                }
+                _ => Ok(outcome),
+            }
+        }
+        Command::Reset { keep_buzzer, yes } => {
+            let guarded = session
+                .config
+                .flag("confirm_reset")
+                .map_err(ControlError::Config)?;
+            if guarded && !yes && !session.options.assume_device {
+                confirm_reset()?;
+            }
+            session.send(reset_report(keep_buzzer))
+        }
+        Command::Report { bytes, probe_safe } => {
+            if bytes.len() != REPORT_LEN {
+                return Err(ControlError::InvalidArg("report must be 8 bytes".into()));
+            }
+            let mut report = [0u8; REPORT_LEN];
+            report.copy_from_slice(&bytes);
+            let outcome = session.send(report)?;
+            if !probe_safe
+                || session.options.simulate.is_some()
+                || !matches!(outcome, Outcome::Delivered(_))
+            {
+                return Ok(outcome);
+            }
+            // A tower wedged by the report stops answering reads; put it back
+            // to a known state before saying so. Towers that cannot be read
+            // back at all keep the report and go unprobed.
+            match session.read_report() {
+                Ok(_) => Ok(outcome),
+                Err(err) if !matches!(err, ControlError::ReadTimeout(_)) => {
+                    if !session.options.quiet {
+                        eprintln!("warning: probe skipped: {err}");
+                    }
+                    Ok(outcome)
+                }
+                Err(err) => {
+                    session.disconnect();
+                    let reset = session.send(reset_report(false)).err().map(Box::new);
+                    Err(ControlError::ProbeUnsafe {
+                        report,
+                        cause: Box::new(err),
+                        reset,
+                    })
+                }
+            }
+        }
+        Command::Decode {
+            bytes,
+            explain_bits,
+        } => {
+            let report: [u8; REPORT_LEN] = bytes
+                .try_into()
+                .map_err(|_| ControlError::InvalidArg("report must be 8 bytes".into()))?;
+            let text = match explain_bits {
+                true => explain_bits_table(&report).trim_end().to_string(),
+                false => decode_report(&report).to_string(),
+            };
+            Ok(Outcome::Output(text, 0))
+        }
+        Command::Status { exit_code } => {
+            let state = decode_report(&session.read_report()?);
+            let text = match session.options.format {
+                OutputFormat::Json => state.to_json(),
+                OutputFormat::Text => state.to_string(),
+            };
+            if !exit_code {
+                return Ok(Outcome::Output(text, 0));
+            }
+            let code = status_exit_code(session.config, &state)?;
+            Ok(match session.options.verbose > 0 {
+                true => Outcome::Output(text, code),
+                false => Outcome::Exit(code),
+            })
+        }
+        Command::Ensure { color, state } => {
+            let report = light_report(&[color], state)?;
+            // Towers that cannot report their state are always written to.
+            if let Ok(current) = session.read_report()
+                && decode_report(&current).leds[usize::from(color)] == nibble(state)
+            {
+                return Ok(Outcome::Unchanged);
+            }
+            session.send(report)
+        }
+        Command::Monitor { interval_ms, json } => {
+            let _holder = session.hold("monitor", None);
+            let mut previous: Option<[u8; REPORT_LEN]> = None;
+            loop {
+                // The first read doubles as the check that the tower
+                // supports read-back at all.
+                let report = session.read_report()?;
+                let changes = previous.map(|previous| report_diff(&previous, &report));
+                if changes.as_ref().is_none_or(|changes| !changes.is_empty()) {
+                    let state = decode_report(&report);
+                    if session.options.mirror && !json {
+                        println!("{}", mirror_line(&state));
+                    }
+                    if json {
+                        println!("{}", state.to_json());
+                    } else if let Some(template) = &session.options.report_template {
+                        println!("{}", fill_template(template, &report).unwrap_or_default());
+                    } else if let Some(changes) = changes {
+                        let changes: Vec<_> = changes.iter().map(|c| c.to_string()).collect();
+                        println!("{state} ({})", changes.join(", "));
+                    } else {
+                        println!("{state}");
+                    }
+                    previous = Some(report);
+                }
+                thread::sleep(Duration::from_millis(interval_ms));
+            }
+        }
+        Command::OnChange { exec, interval_ms } => {
+            let _holder = session.hold("on-change", None);
+            let mut previous = session.read_report()?;
+            loop {
+                thread::sleep(Duration::from_millis(interval_ms));
+                let report = session.read_report()?;
+                let changes = report_diff(&previous, &report);
+                if changes.is_empty() {
+                    continue;
+                }
+                let command = exec_command(&exec, &report, &changes);
+                match std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(&command)
+                    .status()
+                {
+                    Ok(status) if !status.success() => eprintln!("on-change: `{command}` {status}"),
+                    Ok(_) => {}
+                    Err(err) => eprintln!("on-change: `{command}`: {err}"),
+                }
+                previous = report;
+            }
+        }
+        Command::PollButton {
+            byte,
+            mask,
+            exec,
+            interval_ms,
+            debounce_ms,
+        } => {
+            if mask == 0 {
+                return Err(ControlError::InvalidArg(
+                    "--mask must select at least one bit".into(),
+                ));
+            }
+            let _holder = session.hold("poll-button", None);
+            poll_button(
+                session,
+                |report: &[u8; REPORT_LEN]| report[byte] & mask != 0,
+                &exec,
+                Duration::from_millis(interval_ms),
+                Duration::from_millis(debounce_ms),
+            )
+        }
+        Command::Pulse {
+            color,
+            ease,
+            duration_ms,
+            preview,
+        } => {
+            let frames = pulse_frames(color, ease, Duration::from_millis(duration_ms))?;
+            if preview {
+                print!("{}", preview_timeline(&frames));
+                return Ok(Outcome::Silent);
+            }
+            play(session, &frames, thread::sleep)
+        }
+        Command::Gradient {
+            from,
+            to,
+            duration,
+            repeat,
+            preview,
+        } => {
+            let frames = gradient_frames(session, from, to, duration)?;
+            if preview {
+                print!("{}", preview_timeline(&frames));
+                return Ok(Outcome::Silent);
+            }
+            loop {
+                let outcome = play(session, &frames, thread::sleep)?;
+                if !repeat {
+                    return Ok(outcome);
+                }
+            }
+        }
+        Command::List { json } => {
+            let json = json || session.options.format == OutputFormat::Json;
+            let usb = &session.options.usb;
+            let devices = list_devices(&usb_context()?, usb.vendor_id, &usb.product_ids)?;
+            let text = if json {
+                let items: Vec<_> = devices.iter().map(DeviceInfo::to_json).collect();
+                format!("[{}]", items.join(","))
+            } else if devices.is_empty() {
+                "no Patlite devices found".into()
+            } else {
+                let lines: Vec<_> = devices.iter().map(DeviceInfo::to_string).collect();
+                lines.join("\n")
+            };
+            // Scripts can test for an attached tower by the exit status.
+            Ok(Outcome::Output(text, devices.is_empty().into()))
+        }
+        Command::Aliases { json } => {
+            let site: Vec<_> = SITE_COLOR_ALIASES
+                .get()
+                .into_iter()
+                .flatten()
+                .map(|(name, color)| (name.as_str(), *color))
+                .collect();
+            let tables: Vec<(&str, Vec<(&str, u8)>)> = ALIAS_TABLES
+                .iter()
+                .map(|&(kind, table)| (kind, table.to_vec()))
+                .chain((!site.is_empty()).then_some(("site_color", site)))
+                .collect();
+            if json {
+                let tables: Vec<_> = tables
+                    .iter()
+                    .map(|(kind, table)| {
+                        let entries: Vec<_> = table
+                            .iter()
+                            .map(|(name, value)| format!("{}:{value}", json_string(name)))
+                            .collect();
+                        format!("{}:{{{}}}", json_string(kind), entries.join(","))
+                    })
+                    .collect();
+                println!("{{{}}}", tables.join(","));
+            } else {
+                for (kind, table) in &tables {
+                    for (name, value) in table {
+                        println!("{kind} {name} {value:#x}");
+                    }
+                }
+            }
+            Ok(Outcome::Silent)
+        }
+        Command::States { json } => {
+            let mut out = io::BufWriter::new(io::stdout().lock());
+            for state in possible_states() {
+                let report = state.to_report();
+                let written = if json {
+                    writeln!(
+                        out,
+                        "{{\"report\":\"{}\",\"state\":{}}}",
+                        format_report(&report),
+                        state.to_json()
+                    )
+                } else {
+                    writeln!(out, "{}  {state}", format_report(&report))
+                };
+                written.map_err(ControlError::Io)?;
+            }
+            out.flush().map_err(ControlError::Io)?;
+            Ok(Outcome::Silent)
+        }
+        Command::Info { json } => {
+            print_info(json);
+            Ok(Outcome::Silent)
+        }
+        Command::Save { path } => {
+            let report = decode_report(&session.read_report()?).to_report();
+            fs::write(&path, format!("{}\n", format_report(&report)))
+                .map_err(|err| ControlError::File(path, err))?;
+            Ok(Outcome::Sent)
+        }
+        Command::Restore { path } => session.send(read_report_file(&path)?),
+        Command::Heartbeat {
+            color,
+            interval_ms,
+            on_ms,
+        } => {
+            if on_ms >= interval_ms {
+                return Err(ControlError::InvalidArg(
+                    "--on-ms must be shorter than --interval-ms".into(),
+                ));
+            }
+            let _holder = session.hold("heartbeat", None);
+            heartbeat(
+                session,
+                color,
+                Duration::from_millis(interval_ms),
+                Duration::from_millis(on_ms),
+            )
+        }
+        Command::Blink {
+            color,
+            period_ms,
+            count,
+        } => {
+            let _holder = session.hold("blink", None);
+            blink(session, color, Duration::from_millis(period_ms), count)
+        }
+        Command::Melody { notes } => {
+            let _holder = session.hold("melody", None);
+            melody(session, &notes)
+        }
+        Command::Sweep {
+            byte,
+            from,
+            to,
+            step_ms,
+            base,
+            reset_to_base,
+        } => {
+            let base: [u8; REPORT_LEN] = match base {
+                Some(bytes) => bytes
+                    .try_into()
+                    .map_err(|_| ControlError::InvalidArg("--base must be 8 bytes".into()))?,
+                None => [0; REPORT_LEN],
+            };
+            let _holder = session.hold("sweep", None);
+            let swept = sweep(
+                session,
+                base,
+                byte,
+                from,
+                to,
+                Duration::from_millis(step_ms),
+            );
+            if reset_to_base {
+                session.send(base)?;
+            }
+            swept
+        }
+        Command::Bench { duration } => bench(session, duration),
+        Command::RemoteInfo => {
+            if let Some(err) = session.options.no_tower() {
+                return Err(err);
+            }
+            let started = Instant::now();
+            session.transport.connect()?;
+            let opened = started.elapsed();
+            let (bus, address) = session.transport.location().ok_or_else(|| {
+                ControlError::InvalidArg(
+                    "this needs libusb access to the tower; drop --hidraw".into(),
+                )
+            })?;
+            let firmware = session
+                .transport
+                .firmware()
+                .map_or_else(|| "-".into(), firmware_version);
+
+            // Every field kept: exercises the write path without a visible change.
+            let keep = build_report(BUZZER_KEEP, 0, LED_KEEP_PAIR, LED_KEEP_PAIR, LED_KEEP_HIGH);
+            let started = Instant::now();
+            session.transport.send(&keep)?;
+            let write = started.elapsed();
+
+            let transport = if is_usbip_bus(bus) {
+                "usbip (vhci_hcd)"
+            } else {
+                "local"
+            };
+            println!(
+                "bus={bus:03} address={address:03} firmware={firmware} transport={transport} timeout={:?}",
+                session.options.usb.timeout
+            );
+            println!("open+claim={opened:.2?} write={write:.2?}");
+            Ok(Outcome::Silent)
+        }
+        Command::Stream { max_rate } => {
+            let _holder = session.hold("stream", None);
+            stream(session, max_rate)
+        }
+        Command::RunFile {
+            path,
+            continue_on_error,
+        } => run_file(session, &path, continue_on_error),
+        Command::Batch { continue_on_error } => {
+            run_lines(session, io::stdin().lock().lines(), continue_on_error)
+        }
+        #[cfg(target_os = "linux")]
+        Command::InstallService { mode, output, args } => {
+            let exe = std::env::current_exe().map_err(ControlError::Io)?;
+            let unit = service_unit(&exe, mode, &args);
+            match output {
+                Some(path) => {
+                    fs::write(&path, unit).map_err(|err| ControlError::File(path, err))?
+                }
+                None => print!("{unit}"),
+            }
+            Ok(Outcome::Silent)
+        }
+        Command::Scene { name, minimal } => {
+            let report = session.cvd(scene_state(session.config, &name)?.to_report())?;
+            if !minimal {
+                return session.send(report);
+            }
+            // Towers that cannot report their state get the whole scene.
+            let Ok(current) = session.read_report() else {
+                return session.send(report);
+            };
+            let target = decode_report(&report);
+            match minimal_report(&current, &target) {
+                Some(report) => session.send(report),
+                None => Ok(Outcome::Unchanged),
+            }
+        }
+        Command::SceneEdit { name } => {
+            let entries = prompt_scene(&name)?;
+            let path = match session.config.paths.last() {
+                Some(path) => path.clone(),
+                None => config::default_path().ok_or_else(|| {
+                    ControlError::Config("no config file path (HOME is not set)".into())
+                })?,
+            };
+            save_scene(&path, &name, &entries)?;
+            println!("scene `{name}` saved to {}", path.display());
+            Ok(Outcome::Silent)
+        }
+        Command::CheckConfig => {
+            let problems = check_config(session.config);
+            for problem in &problems {
+                eprintln!("{problem}");
+            }
+            if !problems.is_empty() {
+                return Err(ControlError::Config(format!(
+                    "{} problem(s) found",
+                    problems.len()
+                )));
+            }
+            Ok(Outcome::Sent)
+        }
+        Command::Completions { shell: Shell::Bash } => {
+            print!("{}", bash_completion());
+            Ok(Outcome::Silent)
+        }
+        Command::Stress {
+            processes,
+            iterations,
+        } => stress(session.options, processes, iterations),
+        Command::StressWorker { iterations } => {
+            let keep = build_report(BUZZER_KEEP, 0, LED_KEEP_PAIR, LED_KEEP_PAIR, LED_KEEP_HIGH);
+            let (mut writes, mut retried, mut busy_retries, mut failures) = (0, 0, 0, 0);
+            for _ in 0..iterations {
+                // A fresh open each time, so every write contends for the claim.
+                let result = open_usb(session.options, &mut None).and_then(|mut device| {
+                    device.send(&keep)?;
+                    Ok(device.setup_timing().claim_attempts)
+                });
+                match result {
+                    Ok(attempts) => {
+                        writes += 1;
+                        let extra = attempts - session.options.usb.interfaces.len();
+                        retried += usize::from(extra > 0);
+                        busy_retries += extra;
+                    }
+                    Err(err) => {
+                        failures += 1;
+                        eprintln!("stress worker {}: {err}", std::process::id());
+                    }
+                }
+            }
+            println!("{writes} {retried} {busy_retries} {failures}");
+            Ok(Outcome::Silent)
+        }
+        Command::CompleteScenes => {
+            for (name, _) in session
+                .config
+                .table("scenes")
+                .map_err(ControlError::Config)?
+            {
+                println!("{name}");
+            }
+            Ok(Outcome::Silent)
+        }
+        Command::Repl { reset_on_exit } => {
+            let _holder = session.hold("repl", None);
+            repl(session, reset_on_exit)
+        }
+        #[cfg(unix)]
+        Command::Fifo { path } => {
+            let at =
+                std::path::absolute(&path).map_err(|err| ControlError::File(path.clone(), err))?;
+            let _holder = session.hold("fifo", Some(holder::Serves::Fifo(at)));
+            fifo(session, &path)
+        }
+        #[cfg(unix)]
+        Command::Socket { path } => {
+            let at =
+                std::path::absolute(&path).map_err(|err| ControlError::File(path.clone(), err))?;
+            let _holder = session.hold("socket", Some(holder::Serves::Socket(at)));
+            socket::serve(session, &path)
+        }
+    }
+}
+
+/// Runs until a non-recoverable error. USB failures are logged and the device
+/// is reopened on the next beat, so an unplugged tower resumes when it returns.
+fn heartbeat(
+    session: &mut Session,
+    color: u8,
+    interval: Duration,
+    on_time: Duration,
+) -> ControlResult<Outcome> {
+    let on = light_report(&[color], LED_ON)?;
+    let off = light_report(&[color], LED_OFF)?;
+    loop {
+        let beat = session.send(on).and_then(|_| {
+            thread::sleep(on_time);
+            session.send(off)
+        });
+        match beat {
+            Ok(_) => thread::sleep(interval - on_time),
+            Err(err) if err.is_recoverable() => {
+                eprintln!("heartbeat: {err}; retrying");
+                session.disconnect();
+                thread::sleep(interval);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Alternates `color` between solid and off `count` times, `period` apart,
+/// ending off. Ctrl-C or SIGTERM puts back the state read before the first
+/// frame, or just switches the lamp off on towers that cannot be read.
+fn blink(session: &mut Session, color: u8, period: Duration, count: u32) -> ControlResult<Outcome> {
+    let frames = [
+        light_report(&[color], LED_ON)?,
+        light_report(&[color], LED_OFF)?,
+    ];
+    let _catch = interrupt::catch();
+    let prior = prior_state(session);
+    let mut outcome = Outcome::Silent;
+    // Two frames per blink; u64 so the largest --count cannot overflow.
+    let total = u64::from(count) * 2;
+    for index in 0..total {
+        let report = frames[(index % 2) as usize];
+        outcome = session.send(report)?;
+        print_frame(&outcome, session.options);
+        if index + 1 == total {
+            break;
+        }
+        if interrupt::sleep(period) {
+            let restored = match prior {
+                Some(prior) => session.send(prior)?,
+                None if report == frames[0] => session.send(frames[1])?,
+                None => Outcome::Silent,
+            };
+            print_frame(&restored, session.options);
+            return Err(ControlError::Interrupted);
+        }
+    }
+    // Dry-run and validated frames were printed as they went.
+    match outcome {
+        Outcome::Validated(_) | Outcome::DryRun(_) => Ok(Outcome::Silent),
+        outcome => Ok(outcome),
+    }
+}
+
+/// Prints a frame's report as it goes under `--dry-run` or `--assume-device`,
+/// where a long-running command would otherwise only show its last.
+fn print_frame(outcome: &Outcome, options: &SendOptions) {
+    if let Outcome::Validated(_) | Outcome::DryRun(_) = outcome {
+        print_outcome(outcome, options);
+    }
+}
+
+/// The tower's state before a long-running command changes it, to restore
+/// if the command is interrupted. `None` when it cannot be read back within
+/// `PRIOR_STATE_TIMEOUT_MS`, as under `--hidraw`, `--dry-run` or on towers
+/// without read-back.
+fn prior_state(session: &mut Session) -> Option<[u8; REPORT_LEN]> {
+    let timeout = Duration::from_millis(PRIOR_STATE_TIMEOUT_MS);
+    let report = session.transport.read_report_within(timeout).ok()?;
+    Some(decode_report(&session.wire_order(report)).to_report())
+}
+
+/// One `melody` step: the buzzer on at pitch A `pitch` for `duration`.
+#[derive(Clone, Debug)]
+struct Note {
+    pitch: u8,
+    duration: Duration,
+}
+
+/// Sounds each note in turn with the buzzer held on, then switches it off.
+/// The lamps are kept. Ctrl-C or SIGTERM puts back the state read before
+/// the first note, or silences the buzzer on towers that cannot be read.
+fn melody(session: &mut Session, notes: &[Note]) -> ControlResult<Outcome> {
+    let _catch = interrupt::catch();
+    let prior = prior_state(session);
+    let quiet = build_report(
+        BUZZER_OFF,
+        (PITCH_OFF << 4) | PITCH_OFF,
+        LED_KEEP_PAIR,
+        LED_KEEP_PAIR,
+        LED_KEEP_HIGH,
+    );
+    for note in notes {
+        let pitch = (note.pitch << 4) | BUZZER_PITCH_DEFAULT_B;
+        let report = build_report(
+            BUZZER_ON,
+            pitch,
+            LED_KEEP_PAIR,
+            LED_KEEP_PAIR,
+            LED_KEEP_HIGH,
+        );
+        let outcome = session.send(report)?;
+        print_frame(&outcome, session.options);
+        if interrupt::sleep(note.duration) {
+            print_frame(&session.send(prior.unwrap_or(quiet))?, session.options);
+            return Err(ControlError::Interrupted);
+        }
+    }
+    session.send(quiet)
+}
+
+/// Writes as fast as the tower accepts for `duration`. Failed writes are
+/// counted rather than fatal, so a flaky link shows up in the summary.
+fn bench(session: &mut Session, duration: Duration) -> ControlResult<Outcome> {
+    let reports = [reset_report(false), light_report(&[COLOR_RED], LED_ON)?]
+        .map(|report| session.wire_order(report));
+    if let Some(err) = session.options.no_tower() {
+        return Err(err);
+    }
+    session.transport.connect()?;
+    let device = &mut *session.transport;
+
+    let mut latencies = Vec::new();
+    let mut errors = 0usize;
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        let report = &reports[(latencies.len() + errors) % reports.len()];
+        let write_started = Instant::now();
+        match device.send(report) {
+            Ok(_) => latencies.push(write_started.elapsed()),
+            Err(err) => {
+                errors += 1;
+                if errors == 1 {
+                    eprintln!("bench: {err}");
+                }
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+
+    let writes = latencies.len();
+    println!(
+        "writes={writes} errors={errors} rate={:.1}/s",
+        writes as f64 / elapsed.as_secs_f64()
+    );
+    if let (Some(min), Some(max)) = (latencies.iter().min(), latencies.iter().max()) {
+        let avg = latencies.iter().sum::<Duration>() / writes as u32;
+        println!("latency min={min:.2?} avg={avg:.2?} max={max:.2?}");
+    }
+    Ok(Outcome::Silent)
+}
+
+/// Writes `base` once per value from `from` to `to`, with that value in
+/// `byte`, pausing `step` after each. Ctrl-C ends the sweep early.
+fn sweep(
+    session: &mut Session,
+    base: [u8; REPORT_LEN],
+    byte: usize,
+    from: u8,
+    to: u8,
+    step: Duration,
+) -> ControlResult<Outcome> {
+    let values: Box<dyn Iterator<Item = u8>> = if from <= to {
+        Box::new(from..=to)
+    } else {
+        Box::new((to..=from).rev())
+    };
+    for value in values {
+        let mut report = base;
+        report[byte] = value;
+        println!("byte {byte} = {value:#04x} ({value})");
+        // Undefined values are the point of a sweep, so under
+        // --assume-device they are reported rather than fatal.
+        match session.send(report) {
+            Ok(outcome @ (Outcome::Validated(_) | Outcome::DryRun(_))) => {
+                print_outcome(&outcome, session.options)
+            }
+            Ok(_) => {}
+            Err(err @ ControlError::InvalidReport(..)) => eprintln!("sweep: {err}"),
+            Err(err) => return Err(err),
+        }
+        if interrupt::sleep(step) {
+            return Err(ControlError::Interrupted);
+        }
+    }
+    Ok(Outcome::Sent)
+}
+
+/// Runs `processes` copies of `__stress-worker` at once against the same
+/// tower and sums up what they saw.
+fn stress(options: &SendOptions, processes: usize, iterations: usize) -> ControlResult<Outcome> {
+    let exe = std::env::current_exe().map_err(ControlError::Io)?;
+    let usb = &options.usb;
+    let mut selection = Vec::new();
+    if let Some(serial) = &usb.serial {
+        selection.extend(["--serial".to_string(), serial.clone()]);
+    }
+    if let Some((bus, address)) = usb.location {
+        selection.extend([
+            "--bus".to_string(),
+            bus.to_string(),
+            "--address".to_string(),
+            address.to_string(),
+        ]);
+    }
+    selection.extend(["--vendor-id".to_string(), format!("{:#06x}", usb.vendor_id)]);
+    selection.extend([
+        "--claim-retries".to_string(),
+        usb.claim_retries.to_string(),
+        "--claim-retry-delay".to_string(),
+        usb.claim_retry_delay.as_millis().to_string(),
+    ]);
+    // Only `--product-id` narrows the list to one model.
+    if let &[product_id] = usb.product_ids.as_slice() {
+        selection.extend(["--product-id".to_string(), format!("{product_id:#06x}")]);
+    }
+
+    let started = Instant::now();
+    let workers = (0..processes)
+        .map(|_| {
+            std::process::Command::new(&exe)
+                .args(&selection)
+                .args(["__stress-worker", "--iterations", &iterations.to_string()])
+                .stdout(std::process::Stdio::piped())
+                .spawn()
+        })
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(ControlError::Io)?;
+
+    let mut totals = [0usize; 4];
+    for worker in workers {
+        let output = worker.wait_with_output().map_err(ControlError::Io)?;
+        let counts: Vec<usize> = String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .filter_map(|count| count.parse().ok())
+            .collect();
+        match counts.as_slice() {
+            &[writes, retried, busy_retries, failures] => {
+                for (total, count) in
+                    totals
+                        .iter_mut()
+                        .zip([writes, retried, busy_retries, failures])
+                {
+                    *total += count;
+                }
+            }
+            _ => totals[3] += iterations,
+        }
+    }
+
+    let [writes, retried, busy_retries, failures] = totals;
+    println!(
+        "{processes} processes x {iterations} iterations in {:.2?}: {writes} written, \
+         {retried} needed claim retries ({busy_retries} Busy retries in all), {failures} failed",
+        started.elapsed()
+    );
+    Ok(Outcome::Silent)
+}
+
+/// Writes each valid stdin line as it arrives, at most `max_rate` a second.
+/// Bad lines are reported and skipped; USB errors end the stream.
+fn stream(session: &mut Session, max_rate: u32) -> ControlResult<Outcome> {
+    if max_rate == 0 {
+        return Err(ControlError::InvalidArg(
+            "--max-rate must be at least 1".into(),
+        ));
+    }
+    let spacing = Duration::from_secs(1) / max_rate;
+    let mut last_write: Option<Instant> = None;
+
+    for (index, line) in io::stdin().lock().lines().enumerate() {
+        let line = line.map_err(ControlError::Io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let report = match json_report(&line)
+            .and_then(|report| session.cvd(report).map_err(|err| err.to_string()))
+        {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("stream: line {}: {err}", index + 1);
+                continue;
+            }
+        };
+
+        if let Some(last) = last_write {
+            thread::sleep(spacing.saturating_sub(last.elapsed()));
+        }
+        match session.send(report) {
+            Ok(outcome @ (Outcome::Validated(_) | Outcome::DryRun(_))) => {
+                print_outcome(&outcome, session.options)
+            }
+            Ok(_) => {}
+            Err(err @ ControlError::InvalidReport(..)) => {
+                eprintln!("stream: line {}: {err}", index + 1)
+            }
+            Err(err) => return Err(err),
+        }
+        last_write = Some(Instant::now());
+    }
+    Ok(Outcome::Silent)
+}
+
+/// Builds a report from a flat JSON object keyed by color, `buzzer`, `limit`,
+/// `pitch_a` and `pitch_b`. States take the CLI aliases or numbers; anything
+/// omitted is kept, and the pitch defaults apply when only one is given.
+fn json_report(line: &str) -> Result<[u8; REPORT_LEN], String> {
+    let mut state = TowerState {
+        leds: [LED_KEEP; COLOR_COUNT],
+        buzzer_pattern: BUZZER_KEEP,
+        buzzer_limit: 0,
+        pitch_a: BUZZER_PITCH_DEFAULT_A,
+        pitch_b: BUZZER_PITCH_DEFAULT_B,
+    };
+    for (key, value) in parse_json_object(line)? {
+        let result = match key.as_str() {
+            "buzzer" => parse_buzzer_pattern(&value).map(|v| state.buzzer_pattern = v),
+            "limit" => parse_nibble(&value).map(|v| state.buzzer_limit = v),
+            "pitch_a" => parse_nibble(&value).map(|v| state.pitch_a = v),
+            "pitch_b" => parse_nibble(&value).map(|v| state.pitch_b = v),
+            _ => match color_alias(&key) {
+                Some(color) => parse_led_state(&value).map(|v| state.leds[usize::from(color)] = v),
+                None => Err("unknown field".into()),
+            },
+        };
+        result.map_err(|err| format!("`{key}`: {err}"))?;
+    }
+    Ok(state.to_report())
+}
+
+/// Parses a single-line JSON object whose values are strings or
+/// non-negative integers, returned as text in input order.
+fn parse_json_object(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut rest = text
+        .trim()
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_suffix('}'))
+        .ok_or("expected a JSON object")?
+        .trim();
+    let mut fields = Vec::new();
+    while !rest.is_empty() {
+        let (key, after) = json_string_token(rest)?;
+        let after = after
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or_else(|| format!("expected `:` after \"{key}\""))?
+            .trim_start();
+        let (value, after) = if after.starts_with('"') {
+            json_string_token(after)?
+        } else {
+            let end = after
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(after.len());
+            if end == 0 {
+                return Err(format!("\"{key}\" must be a string or number"));
+            }
+            (after[..end].to_string(), &after[end..])
+        };
+        fields.push((key, value));
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.is_empty() {
+            return Err(format!("unexpected `{rest}`"));
+        }
+    }
+    Ok(fields)
+}
+
+/// A leading JSON string and what follows it. Only the escapes `json_string`
+/// produces for printable text are understood.
+fn json_string_token(text: &str) -> Result<(String, &str), String> {
+    let body = text.strip_prefix('"').ok_or("expected a string")?;
+    let mut value = String::new();
+    let mut chars = body.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &body[index + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                Some((_, '/')) => value.push('/'),
+                _ => return Err("unsupported escape in string".into()),
+            },
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".into())
+}
+
+/// Whether `bus` hangs off the USB/IP virtual host controller, judged from
+/// its root hub's sysfs path. Always false where there is no sysfs.
+fn is_usbip_bus(bus: u8) -> bool {
+    fs::canonicalize(format!("/sys/bus/usb/devices/usb{bus}"))
+        .is_ok_and(|path| path.to_string_lossy().contains("vhci_hcd"))
+}
+
+/// Reads a report written by `save`: one line of `REPORT_LEN` bytes in the
+/// notation `format_report` produces (decimal is accepted too).
+fn read_report_file(path: &Path) -> ControlResult<[u8; REPORT_LEN]> {
+    let text = fs::read_to_string(path).map_err(|err| ControlError::File(path.into(), err))?;
+    let bytes = text
+        .split_whitespace()
+        .map(parse_byte)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| ControlError::InvalidArg(format!("{}: {err}", path.display())))?;
+    bytes.try_into().map_err(|bytes: Vec<u8>| {
+        ControlError::InvalidArg(format!(
+            "{}: expected {REPORT_LEN} bytes, found {}",
+            path.display(),
+            bytes.len()
+        ))
+    })
+}
+
+/// Sends `report` and, given a hold time, waits it out and then turns off
+/// whatever the report switched on. Ctrl-C cuts the wait short but still
+/// clears the tower before exiting.
+fn send_for(
+    session: &mut Session,
+    report: [u8; REPORT_LEN],
+    hold: Option<Duration>,
+) -> ControlResult<Outcome> {
+    let outcome = session.send(report)?;
+    clear_after(session, report, hold, outcome)
+}
+
+/// `send_for`, or with a stagger one single-lamp report per lamp that
+/// `report` sets, `stagger_ms` apart. Clearing after a hold is one report.
+fn send_lamps(
+    session: &mut Session,
+    report: [u8; REPORT_LEN],
+    stagger_ms: Option<u64>,
+    hold: Option<Duration>,
+) -> ControlResult<Outcome> {
+    let Some(stagger_ms) = stagger_ms else {
+        return send_for(session, report, hold);
+    };
+    let state = decode_report(&report);
+    let mut frames = Vec::new();
+    for (color, &led) in (0u8..).zip(&state.leds) {
+        if led != LED_KEEP {
+            frames.push(Frame {
+                report: light_report(&[color], led)?,
+                hold: Duration::from_millis(stagger_ms),
+            });
+        }
+    }
+    if let Some(last) = frames.last_mut() {
+        last.hold = Duration::ZERO;
+    }
+    let outcome = play(session, &frames, thread::sleep)?;
+    clear_after(session, report, hold, outcome)
+}
+
+/// The waiting half of `send_for`, for callers that sent `report` themselves.
+fn clear_after(
+    session: &mut Session,
+    report: [u8; REPORT_LEN],
+    hold: Option<Duration>,
+    outcome: Outcome,
+) -> ControlResult<Outcome> {
+    let Some(hold) = hold else {
+        return Ok(outcome);
+    };
+    let interrupted = interrupt::sleep(hold);
+    let outcome = session.send(clear_report(&report))?;
+    if interrupted {
+        return Err(ControlError::Interrupted);
+    }
+    Ok(outcome)
+}
+
+/// Buzzer patterns for `--intensity` 1 to 4, by how much of each cycle
+/// they sound: the three beeping patterns sparsest first, then continuous.
+const BUZZER_INTENSITY_PATTERNS: [u8; 4] = [0x2, 0x3, 0x4, 0x1];
+
+/// The pattern for an intensity, with `[buzzer_intensity]` overrides from
+/// the config applied, e.g. `2 = "pattern4"`.
+fn buzzer_intensity_pattern(config: &Config, level: u8) -> ControlResult<u8> {
+    let mut patterns = BUZZER_INTENSITY_PATTERNS;
+    for (key, value) in config
+        .table("buzzer_intensity")
+        .map_err(ControlError::Config)?
+    {
+        let slot = match key.parse::<usize>() {
+            Ok(level @ 1..=4) => &mut patterns[level - 1],
+            _ => {
+                return Err(ControlError::Config(format!(
+                    "[buzzer_intensity]: unknown level `{key}` (expected 1-4)"
+                )));
+            }
+        };
+        *slot = config
+            .string_in("buzzer_intensity", key, value)
+            .and_then(parse_buzzer_pattern)
+            .map_err(|err| ControlError::Config(format!("[buzzer_intensity] {key}: {err}")))?;
+    }
+    Ok(patterns[usize::from(level) - 1])
+}
+
+/// How long a timed buzzer limit lasts, taking each step as one second;
+/// `None` for 0, which sounds until reset.
+fn buzzer_limit_duration(limit: u8) -> Option<Duration> {
+    (limit != 0).then(|| Duration::from_secs(u64::from(nibble(limit))))
+}
+
+/// The report that undoes `report`: every lamp and buzzer it sets goes off,
+/// and everything it keeps stays kept.
+fn clear_report(report: &[u8; REPORT_LEN]) -> [u8; REPORT_LEN] {
+    let mut state = decode_report(report);
+    for led in &mut state.leds {
+        if *led != LED_KEEP {
+            *led = LED_OFF;
+        }
+    }
+    if state.buzzer_pattern != BUZZER_KEEP {
+        state.buzzer_pattern = BUZZER_OFF;
+    }
+    state.buzzer_limit = 0;
+    state.to_report()
+}
+
+#[cfg(unix)]
+mod interrupt {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+    static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+    static CATCHERS: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+
+    /// While held, SIGINT and SIGTERM are caught instead of fatal, so a
+    /// command can tidy up the tower before exiting. Nested catches share
+    /// one flag; the default actions come back when the outermost is dropped.
+    pub struct Catch(());
+
+    pub fn catch() -> Catch {
+        if CATCHERS.fetch_add(1, Ordering::SeqCst) == 0 {
+            INTERRUPTED.store(false, Ordering::SeqCst);
+            let handler = on_interrupt as extern "C" fn(libc::c_int);
+            for signal in SIGNALS {
+                // SAFETY: the handler only stores to an atomic, which is
+                // async-signal-safe.
+                unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+            }
+        }
+        Catch(())
+    }
+
+    impl Drop for Catch {
+        fn drop(&mut self) {
+            if CATCHERS.fetch_sub(1, Ordering::SeqCst) == 1 {
+                for signal in SIGNALS {
+                    // SAFETY: restoring the default disposition has no
+                    // preconditions.
+                    unsafe { libc::signal(signal, libc::SIG_DFL) };
+                }
+            }
+        }
+    }
+
+    /// Sleeps for `duration` under a `catch`; returns whether a signal cut
+    /// it short, or had already arrived while an outer catch was held.
+    pub fn sleep(duration: Duration) -> bool {
+        let _catch = catch();
+        let deadline = Instant::now() + duration;
+        while !INTERRUPTED.load(Ordering::SeqCst) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            thread::sleep(left.min(Duration::from_millis(50)));
+        }
+        INTERRUPTED.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(not(unix))]
+mod interrupt {
+    use std::time::Duration;
+
+    pub struct Catch(());
+
+    pub fn catch() -> Catch {
+        Catch(())
+    }
+
+    pub fn sleep(duration: Duration) -> bool {
+        std::thread::sleep(duration);
+        false
+    }
+}
+
+/// Long-running commands keep the interface claimed for their whole run, so
+/// they leave a note naming themselves, one per tower by bus and address. A
+/// one-shot command that then finds the interface busy can say who holds it
+/// instead of a bare `Busy`, or with `--via-daemon` send itself through the
+/// socket or FIFO the holder serves.
+mod holder {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    fn path((bus, address): (u8, u8)) -> PathBuf {
+        let dir = env::var_os("XDG_RUNTIME_DIR")
+            .filter(|dir| !dir.is_empty())
+            .map_or_else(env::temp_dir, PathBuf::from);
+        dir.join(format!("ptltectl-{bus:03}-{address:03}.holder"))
+    }
+
+    /// Where a `socket` or `fifo` holder takes command lines.
+    pub enum Serves {
+        Socket(PathBuf),
+        Fifo(PathBuf),
+    }
+
+    pub struct Holder {
+        pub pid: u32,
+        pub command: String,
+        pub serves: Option<Serves>,
+    }
+
+    /// Removes the note again when the holding command ends.
+    pub struct Lock(PathBuf);
+
+    impl Lock {
+        /// Best effort: a note that cannot be written only costs the nicer
+        /// error message. A live holder's note is left alone, including this
+        /// process's own when a server runs a command for a client.
+        pub fn acquire(tower: (u8, u8), command: &str, serves: Option<Serves>) -> Option<Lock> {
+            let path = path(tower);
+            if read(&path).is_some_and(|holder| alive(holder.pid)) {
+                return None;
+            }
+            let mut note = format!("{} {command}\n", process::id());
+            match serves {
+                Some(Serves::Socket(at)) => note += &format!("socket {}\n", at.display()),
+                Some(Serves::Fifo(at)) => note += &format!("fifo {}\n", at.display()),
+                None => {}
+            }
+            fs::write(&path, note).ok()?;
+            Some(Lock(path))
+        }
+    }
+
+    impl Drop for Lock {
+        /// Leaves a note another process has written since, so a holder that
+        /// took over a stale note keeps it.
+        fn drop(&mut self) {
+            if read(&self.0).is_some_and(|holder| holder.pid == process::id()) {
+                let _ = fs::remove_file(&self.0);
+            }
+        }
+    }
+
+    /// The live holder of the tower at `tower`, ignoring notes left by a
+    /// process that has since died.
+    pub fn current(tower: (u8, u8)) -> Option<Holder> {
+        read(&path(tower)).filter(|holder| alive(holder.pid))
+    }
+
+    fn read(path: &Path) -> Option<Holder> {
+        let text = fs::read_to_string(path).ok()?;
+        let mut lines = text.lines();
+        let (pid, command) = lines.next()?.split_once(' ')?;
+        let serves = lines
+            .next()
+            .and_then(|line| line.split_once(' '))
+            .and_then(|(kind, at)| match kind {
+                "socket" => Some(Serves::Socket(at.into())),
+                "fifo" => Some(Serves::Fifo(at.into())),
+                _ => None,
+            });
+        Some(Holder {
+            pid: pid.parse().ok()?,
+            command: command.to_string(),
+            serves,
+        })
+    }
+
+    #[cfg(unix)]
+    fn alive(pid: u32) -> bool {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return false;
+        };
+        // SAFETY: signal 0 only checks that the process exists.
+        unsafe { libc::kill(pid, 0) == 0 }
+    }
+
+    #[cfg(not(unix))]
+    fn alive(_pid: u32) -> bool {
+        true
+    }
+}
+
+/// Per-tower state file `<kind>-<serial>` under `$XDG_STATE_HOME/ptltectl`
+/// (falling back to `~/.local/state`).
+fn state_path(kind: &str, serial: &str) -> ControlResult<PathBuf> {
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".local").join("state"),
+            None => {
+                return Err(ControlError::InvalidArg(
+                    "per-tower state files need $XDG_STATE_HOME or $HOME".into(),
+                ));
+            }
+        },
+    };
+    let dir = base.join("ptltectl");
+    fs::create_dir_all(&dir).map_err(|err| ControlError::File(dir.clone(), err))?;
+    let name: String = serial
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    Ok(dir.join(format!("{kind}-{name}")))
+}
+
+/// Time since the stamp in `path` was written; `None` when there is no
+/// readable stamp, which lets the buzzer sound.
+fn since_last_buzz(path: &Path) -> Option<Duration> {
+    let millis: u64 = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some(now.saturating_sub(Duration::from_millis(millis)))
+}
+
+fn record_buzz(path: &Path) -> ControlResult<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    write_atomically(path, &format!("{}\n", now.as_millis()))
+}
+
+/// Replaces `path` through a rename so a concurrent reader never sees a
+/// half-written file.
+fn write_atomically(path: &Path, contents: &str) -> ControlResult<()> {
+    let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&partial, contents)
+        .and_then(|()| fs::rename(&partial, path))
+        .map_err(|err| ControlError::File(path.into(), err))
+}
+
+/// Fills every kept lamp in `report` from the lamps last sent to this tower,
+/// as cached in `path`. The buzzer is never merged, since replaying it would
+/// sound it again.
+fn merge_cached_lamps(path: &Path, report: [u8; REPORT_LEN]) -> ControlResult<[u8; REPORT_LEN]> {
+    if !path.exists() {
+        return Ok(report);
+    }
+    let cached = decode_report(&read_report_file(path)?);
+    let mut state = decode_report(&report);
+    for (led, cached) in state.leds.iter_mut().zip(cached.leds) {
+        if *led == LED_KEEP {
+            *led = cached;
+        }
+    }
+    Ok(state.to_report())
+}
+
+/// Records the lamps of a sent report for `merge_cached_lamps`.
+fn cache_lamps(path: &Path, report: &[u8; REPORT_LEN]) -> ControlResult<()> {
+    let mut state = decode_report(report);
+    state.buzzer_pattern = BUZZER_KEEP;
+    state.buzzer_limit = 0;
+    write_atomically(path, &format!("{}\n", format_report(&state.to_report())))
+}
+
+/// A report to send and how long to leave it in place before the next one.
+struct Frame {
+    report: [u8; REPORT_LEN],
+    hold: Duration,
+}
+
+/// Sends each frame and waits out its hold with `sleep`, normally
+/// `thread::sleep`; tests pass one that only records the holds.
+fn play(
+    session: &mut Session,
+    frames: &[Frame],
+    mut sleep: impl FnMut(Duration),
+) -> ControlResult<Outcome> {
+    let mut outcome = Outcome::Silent;
+    for frame in frames {
+        outcome = session.send(frame.report)?;
+        sleep(frame.hold);
+    }
+    Ok(outcome)
+}
+
+/// Runs `exec` whenever `pressed` settles on a new value. The first read
+/// fails with a clear error on towers without read-back, so a model that has
+/// no button at all is caught before the loop starts.
+fn poll_button(
+    session: &mut Session,
+    pressed: impl Fn(&[u8; REPORT_LEN]) -> bool,
+    exec: &str,
+    interval: Duration,
+    debounce: Duration,
+) -> ControlResult<Outcome> {
+    let mut settled = pressed(&session.read_report()?);
+    let mut candidate: Option<Instant> = None;
+    loop {
+        thread::sleep(interval);
+        let level = pressed(&session.read_report()?);
+        if level == settled {
+            candidate = None;
+            continue;
+        }
+        let since = *candidate.get_or_insert_with(Instant::now);
+        if since.elapsed() < debounce {
+            continue;
+        }
+        settled = level;
+        candidate = None;
+
+        let event = if level { "press" } else { "release" };
+        let command = exec.replace("{event}", &shell_quote(event));
+        match std::process::Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .status()
+        {
+            Ok(status) if !status.success() => eprintln!("poll-button: `{command}` {status}"),
+            Ok(_) => {}
+            Err(err) => eprintln!("poll-button: `{command}`: {err}"),
+        }
+    }
+}
+
+/// `template` with its placeholders replaced by single-quoted words, so the
+/// state text reaches the command as one argument whatever it contains.
+fn exec_command(template: &str, report: &[u8; REPORT_LEN], changes: &[FieldChange]) -> String {
+    let state = decode_report(report);
+    let changes: Vec<_> = changes.iter().map(|change| change.to_string()).collect();
+    template
+        .replace("{state}", &shell_quote(&state.to_string()))
+        .replace("{changes}", &shell_quote(&changes.join(", ")))
+        .replace("{json}", &shell_quote(&state.to_json()))
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Off, the four hardware patterns, then solid: the closest the tower gets to
+/// a brightness ramp.
+const PULSE_STEPS: [u8; 6] = [0x0, 0x2, 0x3, 0x4, 0x5, 0x1];
+
+/// Columns a preview timeline is scaled to.
+const PREVIEW_WIDTH: u32 = 60;
+
+/// Renders frames as one row per lamp they touch, a column per time slice:
+/// `_` off, `#` on, `1`-`4` the blink patterns, `.` not yet set. Every frame
+/// gets at least one column, so instantaneous steps stay visible.
+fn preview_timeline(frames: &[Frame]) -> String {
+    let total: Duration = frames.iter().map(|frame| frame.hold).sum();
+    let slice = (total / PREVIEW_WIDTH).max(Duration::from_millis(1));
+    let symbol = |led: u8| match led {
+        LED_OFF => '_',
+        LED_ON => '#',
+        0x2..=0x5 => char::from(b'1' + led - 0x2),
+        _ => '?',
+    };
+
+    let mut rows: [Option<String>; COLOR_COUNT] = Default::default();
+    let mut current = [LED_KEEP; COLOR_COUNT];
+    for frame in frames {
+        let columns = (frame.hold.as_millis() / slice.as_millis()).max(1) as usize;
+        let state = decode_report(&frame.report);
+        for (color, &led) in state.leds.iter().enumerate() {
+            if led != LED_KEEP {
+                current[color] = led;
+            }
+        }
+        let drawn = rows.iter().flatten().map(String::len).max().unwrap_or(0);
+        for (color, row) in rows.iter_mut().enumerate() {
+            if current[color] == LED_KEEP {
+                continue;
+            }
+            let row = row.get_or_insert_with(|| ".".repeat(drawn));
+            row.extend(std::iter::repeat_n(symbol(current[color]), columns));
+        }
+    }
+
+    let mut out = String::new();
+    for (color, row) in rows.iter().enumerate() {
+        if let Some(row) = row {
+            out.push_str(&format!("{:<7}{row}\n", color_name(color as u8)));
+        }
+    }
+    out.push_str(&format!(
+        "{:<7}1 column = {slice:.0?}, {total:.2?} in all\n",
+        ""
+    ));
+    out
+}
+
+fn pulse_frames(color: u8, ease: Easing, duration: Duration) -> ControlResult<Vec<Frame>> {
+    let holds = ease_schedule(ease, duration, PULSE_STEPS.len() - 1);
+    PULSE_STEPS
+        .iter()
+        .zip(holds.into_iter().chain([Duration::ZERO]))
+        .map(|(&state, hold)| {
+            Ok(Frame {
+                report: light_report(&[color], state)?,
+                hold,
+            })
+        })
+        .collect()
+}
+
+/// One frame per color from `from` to `to` in tower order (backwards when
+/// `to` comes first), each lamp lit alone for an equal share of `duration`.
+fn gradient_frames(
+    session: &Session,
+    from: u8,
+    to: u8,
+    duration: Duration,
+) -> ControlResult<Vec<Frame>> {
+    let colors: Vec<u8> = if from <= to {
+        (from..=to).collect()
+    } else {
+        (to..=from).rev().collect()
+    };
+    let hold = duration / colors.len() as u32;
+    colors
+        .into_iter()
+        .map(|color| {
+            let mut leds = [LED_OFF; COLOR_COUNT];
+            leds[usize::from(color)] = LED_ON;
+            let state = TowerState {
+                leds,
+                buzzer_pattern: BUZZER_KEEP,
+                buzzer_limit: 0,
+                pitch_a: 0,
+                pitch_b: 0,
+            };
+            Ok(Frame {
+                report: session.cvd(state.to_report())?,
+                hold,
+            })
+        })
+        .collect()
+}
+
+/// Splits `total` into `steps` dwell times whose boundaries follow `ease`.
+fn ease_schedule(ease: Easing, total: Duration, steps: usize) -> Vec<Duration> {
+    (0..steps)
+        .map(|step| {
+            let start = ease.time_at(step as f64 / steps as f64);
+            let end = ease.time_at((step + 1) as f64 / steps as f64);
+            total.mul_f64(end - start)
+        })
+        .collect()
+}
+
+fn repl(session: &mut Session, reset_on_exit: bool) -> ControlResult<Outcome> {
+    session.connect()?;
+
+    // Line editing and up-arrow history for this session; piped input is
+    // read as plain lines with no prompt.
+    let readline_error = |err| match err {
+        ReadlineError::Io(err) => ControlError::Io(err),
+        err => ControlError::Io(io::Error::other(err)),
+    };
+    let mut editor = DefaultEditor::new().map_err(readline_error)?;
+    let prompt = if io::stdin().is_terminal() {
+        "ptltectl> "
+    } else {
+        ""
+    };
+    loop {
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Eof) => break,
+            // Ctrl-C drops the line being typed, as in a shell.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(err) => return Err(readline_error(err)),
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line == "quit" || line == "exit" {
+            break;
+        }
+        editor.add_history_entry(line).map_err(readline_error)?;
+        match parse_line(line) {
+            Ok(command) if is_script_runner(&command) => {
+                eprintln!("Error: scripts and prompts cannot be nested")
+            }
+            Ok(command) => match execute(command, session) {
+                Ok(outcome) => print_outcome(&outcome, session.options),
+                Err(err) => eprintln!("Error: {err}"),
+            },
+            Err(err) => eprint!("{err}"),
+        }
+    }
+
+    if reset_on_exit {
+        session.send(reset_report(false))?;
+    }
+    Ok(Outcome::Silent)
+}
+
+/// Runs lines from the FIFO at `path` until the process is stopped. Each
+/// open blocks until a writer appears and reads until the last one closes,
+/// so `echo ... > path` from any number of shells works in turn.
+#[cfg(unix)]
+fn fifo(session: &mut Session, path: &Path) -> ControlResult<Outcome> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileTypeExt;
+
+    match fs::metadata(path) {
+        Ok(meta) if meta.file_type().is_fifo() => {}
+        Ok(_) => {
+            return Err(ControlError::InvalidArg(format!(
+                "{} exists and is not a named pipe",
+                path.display()
+            )));
+        }
+        Err(_) => {
+            let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+                .map_err(|_| ControlError::InvalidArg("FIFO path contains a NUL byte".into()))?;
+            // SAFETY: `c_path` is a valid NUL-terminated string for the call.
+            if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+                return Err(ControlError::File(path.into(), io::Error::last_os_error()));
+            }
+        }
+    }
+    session.connect()?;
+
+    loop {
+        let file = File::open(path).map_err(|err| ControlError::File(path.into(), err))?;
+        for line in io::BufReader::new(file).lines() {
+            let line = line.map_err(ControlError::Io)?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match serve_line(line, session) {
+                Ok(outcome) => print_outcome(&outcome, session.options),
+                Err(err) => eprintln!("Error: {err}"),
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+mod socket {
+    use super::*;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::Mutex;
+
+    /// Accepts clients until the process is stopped. Each connection gets a
+    /// thread, but every command runs under one lock, so writes from
+    /// different clients reach the single handle one at a time.
+    pub fn serve(session: &mut Session, path: &Path) -> ControlResult<Outcome> {
+        session.connect()?;
+        remove_stale(path)?;
+        let listener =
+            UnixListener::bind(path).map_err(|err| ControlError::File(path.into(), err))?;
+
+        let session = Mutex::new(session);
+        thread::scope(|scope| {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let session = &session;
+                        scope.spawn(move || {
+                            if let Err(err) = handle(stream, session) {
+                                eprintln!("socket: client: {err}");
+                            }
+                        });
+                    }
+                    Err(err) => eprintln!("socket: accept: {err}"),
+                }
+            }
+        });
+        Ok(Outcome::Silent)
+    }
+
+    fn handle(stream: UnixStream, session: &Mutex<&mut Session>) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in io::BufReader::new(stream).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let reply = {
+                let mut session = session.lock().unwrap_or_else(|err| err.into_inner());
+                let result = serve_line(line, &mut session);
+                reply_line(result, session.options)
+            };
+            writeln!(writer, "{}", reply.replace('\n', " "))?;
+        }
+        Ok(())
+    }
+
+    /// One reply per command: `error: <message>` on failure, `exit <code>`
+    /// (then `: <output>` for a read command) for a non-zero exit status,
+    /// else the status line or output, or `ok` when there is none.
+    pub(super) fn reply_line(result: ControlResult<Outcome>, options: &SendOptions) -> String {
+        let outcome = match result {
+            Ok(outcome) => outcome,
+            Err(err) => return format!("error: {err}"),
+        };
+        let line = outcome_line(&outcome, options);
+        match (outcome, line) {
+            (Outcome::Exit(code @ 1..), _) => format!("exit {code}"),
+            (Outcome::Output(text, code @ 1..), _) => format!("exit {code}: {text}"),
+            (_, line) => line.unwrap_or_else(|| "ok".into()),
+        }
+    }
+
+    /// Sends `line` to the server at `path` and waits for its reply.
+    pub fn request(path: &Path, line: &str) -> io::Result<String> {
+        let mut stream = UnixStream::connect(path)?;
+        writeln!(stream, "{line}")?;
+        stream.shutdown(std::net::Shutdown::Write)?;
+        let mut reply = String::new();
+        io::BufReader::new(stream).read_line(&mut reply)?;
+        Ok(reply.trim_end().to_string())
+    }
+
+    /// Clears a socket nobody is listening on any more. Anything else at
+    /// `path`, or a live socket, is left for `bind` to fail on.
+    fn remove_stale(path: &Path) -> ControlResult<()> {
+        let is_socket = fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket());
+        if is_socket && UnixStream::connect(path).is_err() {
+            fs::remove_file(path).map_err(|err| ControlError::File(path.into(), err))?;
+        }
+        Ok(())
+    }
+}
+
+/// `--via-daemon`: one-shot commands go through the `socket` or `fifo` server
+/// holding the tower, which they could not claim themselves.
+mod daemon {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::OpenOptionsExt;
+
+    /// The outcome of `command` run by the server holding the selected tower,
+    /// or None when there is no such server and the command should open the
+    /// tower itself. A FIFO has no reply, so its errors show on the server.
+    pub fn forward(
+        command: &Command,
+        options: &SendOptions,
+        config: &Config,
+    ) -> ControlResult<Option<Outcome>> {
+        if !is_routable(command) || options.no_tower().is_some() || options.hidraw.is_some() {
+            return Ok(None);
+        }
+        let args: Option<Vec<String>> = std::env::args_os()
+            .skip(1)
+            .map(|arg| arg.into_string().ok())
+            .collect();
+        let Some(mut line) = args.and_then(|args| command_line(&args)) else {
+            return Ok(None);
+        };
+        let Some(holder) = serving_holder(options) else {
+            return Ok(None);
+        };
+        // The server has no terminal to ask on, so ask here.
+        if let Command::Reset { yes: false, .. } = command
+            && config.flag("confirm_reset").map_err(ControlError::Config)?
+        {
+            confirm_reset()?;
+            line.push_str(" --yes");
+        }
+        match holder.serves {
+            #[cfg(unix)]
+            Some(holder::Serves::Socket(path)) => match socket::request(&path, &line) {
+                Ok(reply) => reply_outcome(holder.pid, &reply).map(Some),
+                Err(_) => Ok(None),
+            },
+            // Non-blocking, so a FIFO nobody reads any more fails at once.
+            #[cfg(unix)]
+            Some(holder::Serves::Fifo(path)) => {
+                match OpenOptions::new()
+                    .write(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(&path)
+                {
+                    Ok(mut fifo) => {
+                        writeln!(fifo, "{line}").map_err(ControlError::Io)?;
+                        Ok(Some(Outcome::Sent))
+                    }
+                    Err(_) => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// One-shot commands that write or read the tower. The rest need no
+    /// tower, run until stopped, time the local open, or name files the
+    /// server would resolve against its own directory.
+    fn is_routable(command: &Command) -> bool {
+        match command {
+            Command::Light { .. }
+            | Command::Tower { .. }
+            | Command::All { .. }
+            | Command::AlertColor { .. }
+            | Command::Nagios { .. }
+            | Command::Compat { .. }
+            | Command::Buzzer { .. }
+            | Command::Reset { .. }
+            | Command::Report { .. }
+            | Command::Status { .. }
+            | Command::Ensure { .. }
+            | Command::Pulse { .. }
+            | Command::Blink { .. }
+            | Command::Melody { .. }
+            | Command::Sweep { .. }
+            | Command::Scene { .. } => true,
+            Command::Gradient { repeat, .. } => !repeat,
+            _ => false,
+        }
+    }
+
+    /// The subcommand part of the arguments as a server line: the first tail
+    /// that parses on its own, which drops the global flags before it. None
+    /// when a global flag follows the subcommand or an argument holds
+    /// whitespace, neither of which a line can carry.
+    pub fn command_line(args: &[String]) -> Option<String> {
+        let args: Vec<_> = args
+            .iter()
+            .map(String::as_str)
+            .filter(|arg| *arg != "--via-daemon")
+            .collect();
+        if args
+            .iter()
+            .any(|arg| arg.is_empty() || arg.contains(char::is_whitespace))
+        {
+            return None;
+        }
+        (0..args.len())
+            .map(|start| args[start..].join(" "))
+            .find(|line| parse_line(line).is_ok())
+    }
+
+    /// The first live holder serving a socket or FIFO among the towers the
+    /// USB flags select.
+    fn serving_holder(options: &SendOptions) -> Option<holder::Holder> {
+        let usb = &options.usb;
+        let towers: Vec<_> = match usb.location {
+            Some(tower) => vec![tower],
+            None => list_devices(&usb_context().ok()?, usb.vendor_id, &usb.product_ids)
+                .ok()?
+                .into_iter()
+                .filter(|device| usb.serial.is_none() || device.serial == usb.serial)
+                .map(|device| (device.bus, device.address))
+                .collect(),
+        };
+        towers
+            .into_iter()
+            .filter_map(holder::current)
+            .find(|holder| holder.serves.is_some())
+    }
+
+    /// A `socket` reply read back into an outcome, the inverse of
+    /// `socket::reply_line`.
+    pub fn reply_outcome(pid: u32, reply: &str) -> ControlResult<Outcome> {
+        if let Some(message) = reply.strip_prefix("error: ") {
+            return Err(ControlError::Daemon(pid, message.into()));
+        }
+        if let Some(rest) = reply.strip_prefix("exit ") {
+            let (code, text) = rest.split_once(": ").unwrap_or((rest, ""));
+            if let Ok(code) = code.parse() {
+                return Ok(match text {
+                    "" => Outcome::Exit(code),
+                    _ => Outcome::Output(text.into(), code),
+                });
+            }
+        }
+        Ok(match reply {
+            "ok" => Outcome::Sent,
+            _ => Outcome::Output(reply.into(), 0),
+        })
+    }
+}
+
+/// Executes each non-blank, non-`#` line of `path`. Fails on the first bad
+/// line unless `continue_on_error`, in which case every failure is collected
+/// and reported together once the file is done.
+fn run_file(session: &mut Session, path: &Path, continue_on_error: bool) -> ControlResult<Outcome> {
+    let text = fs::read_to_string(path).map_err(|err| ControlError::File(path.into(), err))?;
+    let lines = text.lines().map(|line| Ok(line.to_string()));
+    run_lines(session, lines, continue_on_error)
+}
+
+/// Runs each line as it arrives, so `batch` can follow a pipe that is still
+/// being written. Failures are numbered by line.
+fn run_lines(
+    session: &mut Session,
+    lines: impl Iterator<Item = io::Result<String>>,
+    continue_on_error: bool,
+) -> ControlResult<Outcome> {
+    let mut failures = Vec::new();
+    for (index, line) in lines.enumerate() {
+        let line = line.map_err(ControlError::Io)?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Err(err) = execute_line(line, session) {
+            let failure = ControlError::ScriptLine(index + 1, Box::new(err));
+            if !continue_on_error {
+                return Err(failure);
+            }
+            failures.push(failure);
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(Outcome::Sent)
+    } else {
+        Err(ControlError::ScriptFailed(failures))
+    }
+}
+
+/// A unit that runs `exe <mode> <args>` and keeps it running. The command
+/// rides out unplugs itself where it can; `Restart=` covers the rest.
+#[cfg(target_os = "linux")]
+fn service_unit(exe: &Path, mode: ServiceMode, args: &[String]) -> String {
+    let mut command = vec![exe.display().to_string(), mode.subcommand().to_string()];
+    command.extend(args.iter().cloned());
+    format!(
+        "[Unit]
+Description=Patlite tower {mode} (ptltectl)
+# To start only once a tower is plugged in, tag it in the udev rule with
+# TAG+=\"systemd\", ENV{{SYSTEMD_WANTS}}+=\"ptltectl-{mode}.service\" and add
+# BindsTo=/After= on the resulting dev-bus-usb-*.device unit here.
+After=systemd-udevd.service
+
+[Service]
+ExecStart={}
+Restart=always
+RestartSec=2
+
+[Install]
+WantedBy=multi-user.target
+",
+        command.join(" "),
+        mode = mode.subcommand(),
+    )
+}
+
+/// Completes subcommands and their long flags from the clap definition, and
+/// asks `ptltectl __complete-scenes` for scene names at run time since those
+/// live in the user's config.
+fn bash_completion() -> String {
+    let cli = Cli::command();
+    let globals: Vec<_> = cli
+        .get_arguments()
+        .filter_map(|arg| arg.get_long())
+        .map(|long| format!("--{long}"))
+        .collect();
+
+    let mut cases = String::new();
+    let mut names = Vec::new();
+    for sub in cli.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        names.push(sub.get_name().to_string());
+        let flags: Vec<_> = sub
+            .get_arguments()
+            .filter(|arg| !arg.is_global_set())
+            .filter_map(|arg| arg.get_long())
+            .map(|long| format!("--{long}"))
+            .collect();
+        cases.push_str(&format!(
+            "        {}) words=\"{}\" ;;\n",
+            sub.get_name(),
+            flags.join(" ")
+        ));
+    }
+
+    format!(
+        r#"_ptltectl() {{
+    local cur=${{COMP_WORDS[COMP_CWORD]}} sub="" words i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        [[ ${{COMP_WORDS[i]}} != -* ]] && {{ sub=${{COMP_WORDS[i]}}; break; }}
+    done
+    case $sub in
+        "") words="{names}" ;;
+        scene) words="$(ptltectl __complete-scenes 2>/dev/null)" ;;
+{cases}    esac
+    COMPREPLY=($(compgen -W "$words {globals}" -- "$cur"))
+}}
+complete -F _ptltectl ptltectl
+"#,
+        names = names.join(" "),
+        globals = globals.join(" "),
+    )
+}
+
+/// Parses and runs one line of a script, FIFO or socket client.
+fn execute_line(line: &str, session: &mut Session) -> ControlResult<Outcome> {
+    match parse_line(line) {
+        Ok(command) if is_script_runner(&command) => Err(ControlError::InvalidArg(
+            "scripts and prompts cannot be nested".into(),
+        )),
+        Ok(command) => execute(command, session),
+        Err(err) => Err(ControlError::InvalidArg(clap_message(&err))),
+    }
+}
+
+/// Parses and runs one line from a FIFO or socket client. Commands that run
+/// until stopped are refused: the session is shared, so they would hold the
+/// tower from every other client.
+#[cfg(unix)]
+fn serve_line(line: &str, session: &mut Session) -> ControlResult<Outcome> {
+    if let Ok(command) = parse_line(line)
+        && is_long_running(&command)
+    {
+        let name = line.split_whitespace().next().unwrap_or_default();
+        return Err(ControlError::InvalidArg(format!(
+            "{name} runs until stopped and would hold the tower from other clients; run it on its own"
+        )));
+    }
+    execute_line(line, session)
+}
+
+#[cfg(unix)]
+fn is_long_running(command: &Command) -> bool {
+    match command {
+        Command::Monitor { .. }
+        | Command::OnChange { .. }
+        | Command::PollButton { .. }
+        | Command::Heartbeat { .. }
+        | Command::Stream { .. } => true,
+        Command::Gradient { repeat, .. } => *repeat,
+        _ => false,
+    }
+}
+
+fn is_script_runner(command: &Command) -> bool {
+    match command {
+        Command::Repl { .. }
+        | Command::RunFile { .. }
+        | Command::Batch { .. }
+        | Command::SceneEdit { .. } => true,
+        #[cfg(unix)]
+        Command::Socket { .. } | Command::Fifo { .. } => true,
+        _ => false,
+    }
+}
+
+/// The first line of a clap error without its `error: ` prefix, for reporting
+/// alongside a line number.
+fn clap_message(err: &clap::Error) -> String {
+    let rendered = err.to_string();
+    let first = rendered.lines().next().unwrap_or_default();
+    first.strip_prefix("error: ").unwrap_or(first).to_string()
+}
+
+fn parse_line(line: &str) -> Result<Command, clap::Error> {
+    ScriptLine::try_parse_from(line.split_whitespace()).map(|line| line.command)
+}
+
+/// What every command of one invocation writes through, and the options and
+/// config it runs with.
+struct Session<'a> {
+    options: &'a SendOptions,
+    config: &'a Config,
+    transport: &'a mut (dyn Transport + Send),
+}
+
+impl<'a> Session<'a> {
+    fn new(
+        options: &'a SendOptions,
+        config: &'a Config,
+        transport: &'a mut (dyn Transport + Send),
+    ) -> Self {
+        Session {
+            options,
+            config,
+            transport,
+        }
+    }
+
+    /// Opens the transport now, so that long-running commands fail up front
+    /// rather than on the first line.
+    fn connect(&mut self) -> ControlResult<()> {
+        if self.options.assume_device || self.options.simulate.is_some() {
+            return Ok(());
+        }
+        self.transport.connect()
+    }
+
+    /// Notes this process as the holder of the tower for as long as the lock
+    /// lives. There is nothing to note without a tower, or while it cannot
+    /// be opened; commands that ride out unplugs then run without a note.
+    fn hold(&mut self, command: &str, serves: Option<holder::Serves>) -> Option<holder::Lock> {
+        if self.options.no_tower().is_some() {
+            return None;
+        }
+        self.connect().ok()?;
+        holder::Lock::acquire(self.transport.location()?, command, serves)
+    }
+
+    fn send(&mut self, report: [u8; REPORT_LEN]) -> ControlResult<Outcome> {
+        let cache = match self.options.merge_cached {
+            true => Some(state_path("lamps", &self.cache_serial()?)?),
+            false => None,
+        };
+        let report = match &cache {
+            Some(cache) => merge_cached_lamps(cache, report)?,
+            None => report,
+        };
+        let state = decode_report(&report);
+        if self.options.mirror && state.leds.iter().any(|&led| led != LED_KEEP) {
+            println!("{}", mirror_line(&state));
+        }
+        // Everything printed from here on is the bytes the tower would get.
+        let wire = self.wire_order(report);
+        if self.options.explain {
+            println!("ptltectl report {}", format_report(&wire));
+        }
+        if let Some(language) = self.options.emit_code {
+            println!("{}", language.literal(&wire));
+        }
+        if self.options.emit_shell {
+            print!(
+                "{}",
+                shell_commands(
+                    &wire,
+                    self.options.usb.vendor_id,
+                    self.options.usb.product_ids[0]
+                )
+            );
+        }
+        if self.options.dry_run {
+            return Ok(Outcome::DryRun(wire));
+        }
+        if self.options.assume_device {
+            // Checked before the swap, which only moves nibbles around.
+            let problems = validate_report(&report);
+            return if problems.is_empty() {
+                Ok(Outcome::Validated(wire))
+            } else {
+                Err(ControlError::InvalidReport(report, problems))
+            };
+        }
+        let outcome = match self.options.retry_for {
+            Some(limit) => self.deliver_with_retry(report, limit)?,
+            None => self.deliver(report)?,
+        };
+        // A simulated write never reached a tower, so there is nothing to cache.
+        if let Some(cache) = &cache
+            && self.options.simulate.is_none()
+        {
+            cache_lamps(cache, &report)?;
+        }
+        Ok(outcome)
+    }
+
+    /// Retries transient failures with jittered exponential backoff until
+    /// `limit` has passed, reopening the device each time. Anything else, and
+    /// the last failure once time is up, is returned as is.
+    fn deliver_with_retry(
+        &mut self,
+        report: [u8; REPORT_LEN],
+        limit: Duration,
+    ) -> ControlResult<Outcome> {
+        let started = Instant::now();
+        let wait_for_device = self.options.wait_for_device;
+        let delays = Exponential::from_millis(RETRY_FOR_BASE_MS)
+            .map(|delay| jitter(delay.min(Duration::from_millis(RETRY_FOR_MAX_DELAY_MS))))
+            .take_while(move |delay| started.elapsed() + *delay < limit);
+        retry(delays, || match self.deliver(report) {
+            Ok(outcome) => OperationResult::Ok(outcome),
+            Err(err) if err.is_transient(wait_for_device) => {
+                self.disconnect();
+                OperationResult::Retry(err)
+            }
+            Err(err) => OperationResult::Err(err),
+        })
+        .map_err(|err| err.error)
+    }
+
+    fn deliver(&mut self, report: [u8; REPORT_LEN]) -> ControlResult<Outcome> {
+        if let Some(simulate) = self.options.simulate {
+            return match simulate.error(self.options) {
+                Some(err) => Err(err),
+                None => Ok(Outcome::Delivered(SentInfo {
+                    location: None,
+                    serial: None,
+                    report: self.wire_order(report),
+                })),
+            };
+        }
+        let wire = self.wire_order(report);
+        self.transport.send(&wire)?;
+        Ok(Outcome::Delivered(SentInfo {
+            location: self.transport.location(),
+            serial: self.transport.serial(),
+            report: wire,
+        }))
+    }
+
+    fn read_report(&mut self) -> ControlResult<[u8; REPORT_LEN]> {
+        let report = self.transport.read_report()?;
+        Ok(self.wire_order(report))
+    }
+
+    /// Applies `--swap-nibbles` to the LED bytes (RY, GB and W). The swap is
+    /// its own inverse, so reports read back are put right the same way.
+    fn wire_order(&self, mut report: [u8; REPORT_LEN]) -> [u8; REPORT_LEN] {
+        if self.options.swap_nibbles {
+            for byte in &mut report[4..=6] {
+                *byte = byte.rotate_left(4);
+            }
+        }
+        report
+    }
+
+    /// The serial `--merge-cached` keeps its cache under: the tower's, or
+    /// the `--serial` selection when there is no tower to ask.
+    fn cache_serial(&mut self) -> ControlResult<String> {
+        if self.options.no_tower().is_none() {
+            return self.device_serial();
+        }
+        self.options.usb.serial.clone().ok_or_else(|| {
+            ControlError::InvalidArg(
+                "--merge-cached without a tower needs --serial to find its cache".into(),
+            )
+        })
+    }
+
+    /// Serial number of the open tower, `unknown` when it reports none.
+    fn device_serial(&mut self) -> ControlResult<String> {
+        self.transport.connect()?;
+        Ok(self.transport.serial().unwrap_or_else(|| "unknown".into()))
+    }
+
+    /// Under `--cvd`, swaps every solid-on lamp for its color's pattern so hues
+    /// can be told apart by motion. Lamps given an explicit pattern, off or
+    /// keep are left alone.
+    fn cvd(&self, report: [u8; REPORT_LEN]) -> ControlResult<[u8; REPORT_LEN]> {
+        if !self.options.cvd {
+            return Ok(report);
+        }
+        let patterns = cvd_patterns(self.config)?;
+        let mut state = decode_report(&report);
+        for (led, pattern) in state.leds.iter_mut().zip(patterns) {
+            if *led == LED_ON {
+                *led = pattern;
+            }
+        }
+        Ok(state.to_report())
+    }
+
+    /// Lets go of the tower so the next command opens and claims it afresh.
+    fn disconnect(&mut self) {
+        self.transport.disconnect();
+    }
+}
+
+/// The transport behind a real invocation: a hidraw node under `--hidraw`,
+/// else the tower on USB, opened on first use so commands that fail
+/// validation never touch the device. Adds what the flags ask of every
+/// write: `--accept-short`, `--timing`, `-v` traces and `--report-file`.
+struct Connector<'a> {
+    options: &'a SendOptions,
+    open: Option<Box<dyn Transport + Send>>,
+    report_file: Option<(File, PathBuf)>,
+    // Whether `--timing` applies; a hidraw node has no setup to time.
+    timed: bool,
+    // Reported alongside the first write only, then cleared.
+    setup_timing: Option<SetupTiming>,
+}
+
+impl<'a> Connector<'a> {
+    fn new(options: &'a SendOptions) -> Self {
+        Connector {
+            options,
+            open: None,
+            report_file: None,
+            timed: false,
+            setup_timing: None,
+        }
+    }
+
+    fn transport(&mut self) -> ControlResult<&mut (dyn Transport + Send)> {
+        self.connect()?;
+        Ok(self.open.as_deref_mut().expect("transport opened above"))
+    }
+}
+
+impl Transport for Connector<'_> {
+    fn send(&mut self, report: &[u8; REPORT_LEN]) -> ControlResult<()> {
+        let accept_short = self.options.accept_short;
+        let started = Instant::now();
+        match self.transport()?.send(report) {
+            Ok(()) => self.options.trace(
+                1,
+                format_args!("wrote {REPORT_LEN} bytes: {}", format_report(report)),
+            ),
+            Err(ControlError::ShortWrite { written, expected }) => {
+                self.options.trace(
+                    1,
+                    format_args!("wrote {written} bytes: {}", format_report(report)),
+                );
+                if !accept_short {
+                    return Err(ControlError::ShortWrite { written, expected });
+                }
+                eprintln!(
+                    "warning: tower acknowledged {written} of {expected} bytes; accepted (--accept-short)"
+                );
+            }
+            Err(err) => return Err(err),
+        }
+        let write = started.elapsed();
+
+        if self.timed {
+            match self.setup_timing.take() {
+                Some(setup) => eprintln!("timing: {setup} write={write:.2?}"),
+                None => eprintln!("timing: write={write:.2?}"),
+            }
+        }
+
+        if let Some((file, path)) = self.report_file.as_mut() {
+            append_report_line(file, path, report)?;
+        }
+        Ok(())
+    }
+
+    fn read_report(&mut self) -> ControlResult<[u8; REPORT_LEN]> {
+        self.transport()?.read_report()
+    }
+
+    fn read_report_within(&mut self, timeout: Duration) -> ControlResult<[u8; REPORT_LEN]> {
+        self.transport()?.read_report_within(timeout)
+    }
+
+    fn location(&self) -> Option<(u8, u8)> {
+        self.open.as_ref()?.location()
+    }
+
+    fn serial(&self) -> Option<String> {
+        self.open.as_ref()?.serial()
+    }
+
+    fn firmware(&self) -> Option<rusb::Version> {
+        self.open.as_ref()?.firmware()
+    }
+
+    fn connect(&mut self) -> ControlResult<()> {
+        if self.open.is_some() {
+            return Ok(());
+        }
+        if let Some(err) = self.options.no_tower() {
+            return Err(err);
+        }
+        // Open the audit file up front so an unwritable path never lets a
+        // report reach the tower without being recorded.
+        if self.report_file.is_none()
+            && let Some(path) = &self.options.report_file
+        {
+            self.report_file = Some((open_report_file(path)?, path.clone()));
+        }
+        if let Some(path) = &self.options.hidraw {
+            self.open = Some(Box::new(Hidraw::open(path, self.options)?));
+            return Ok(());
+        }
+        let mut opened = None;
+        let usb = open_usb(self.options, &mut opened).map_err(|err| {
+            let busy = matches!(
+                err,
+                ControlError::Usb {
+                    source: rusb::Error::Busy,
+                    ..
+                }
+            );
+            match opened.and_then(holder::current) {
+                Some(holder) if busy => ControlError::HeldBy(holder.pid, holder.command),
+                _ => err,
+            }
+        })?;
+        self.timed = self.options.timing;
+        self.setup_timing = Some(usb.setup_timing().clone());
+        self.open = Some(Box::new(usb));
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.open = None;
+    }
+}
+
+/// Opens the tower the USB flags select, tracing each step under `-v` and
+/// warning when it had to pick one of several. `opened` gets the tower's bus
+/// and address as soon as it is found, so a failed claim can still name it.
+fn open_usb(options: &SendOptions, opened: &mut Option<(u8, u8)>) -> ControlResult<UsbTransport> {
+    let usb = &options.usb;
+    options.trace(
+        2,
+        format_args!(
+            "timeout {:?}, claim retries {} every {:?}",
+            usb.timeout, usb.claim_retries, usb.claim_retry_delay
+        ),
+    );
+    UsbTransport::open_with(usb, |event| match event {
+        UsbEvent::Ambiguous(count) => {
+            if !options.quiet {
+                eprintln!(
+                    "warning: {count} towers attached, using the first; select one with --serial (see `ptltectl list`) or --product-id"
+                );
+            }
+        }
+        UsbEvent::Opened { bus, address } => {
+            *opened = Some((bus, address));
+            options.trace(1, format_args!("opened bus {bus:03} address {address:03}"))
+        }
+        UsbEvent::AutoDetach(None) => options.trace(
+            1,
+            format_args!("kernel driver detach skipped (--no-auto-detach)"),
+        ),
+        UsbEvent::AutoDetach(Some(Ok(()))) => {
+            options.trace(1, format_args!("kernel driver auto-detach enabled"))
+        }
+        UsbEvent::AutoDetach(Some(Err(err))) => options.trace(
+            1,
+            format_args!("kernel driver auto-detach unavailable: {err}"),
+        ),
+        UsbEvent::Claim {
+            interface,
+            attempt,
+            attempts,
+            result,
+        } => {
+            let status = match result {
+                Ok(()) => "claimed".to_string(),
+                Err(rusb::Error::Busy) if attempt < attempts => {
+                    format!("busy, retrying in {:?}", usb.claim_retry_delay)
+                }
+                Err(rusb::Error::Busy) => "busy, giving up".to_string(),
+                Err(err) => format!("failed: {err}"),
+            };
+            options.trace(
+                1,
+                format_args!(
+                    "claim interface {interface}: attempt {attempt} of {attempts}, {status}"
+                ),
+            );
+        }
+    })
+}
+
+/// A tower written through a Linux hidraw node. The kernel's HID driver
+/// stays bound, so there is nothing to detach or claim.
+struct Hidraw {
+    file: File,
+    path: PathBuf,
+    report_id: u8,
+}
+
+impl Hidraw {
+    fn open(path: &Path, options: &SendOptions) -> ControlResult<Self> {
+        if !cfg!(target_os = "linux") {
+            return Err(ControlError::InvalidArg(
+                "--hidraw is only available on Linux".into(),
+            ));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            let meta = fs::metadata(path).map_err(|err| ControlError::File(path.into(), err))?;
+            if !meta.file_type().is_char_device() {
+                return Err(ControlError::InvalidArg(format!(
+                    "{} is not a hidraw device node",
+                    path.display()
+                )));
+            }
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|err| ControlError::File(path.into(), err))?;
+        options.trace(1, format_args!("opened {}", path.display()));
+        Ok(Hidraw {
+            file,
+            path: path.into(),
+            report_id: options.report_id,
+        })
+    }
+}
+
+impl Transport for Hidraw {
+    fn send(&mut self, report: &[u8; REPORT_LEN]) -> ControlResult<()> {
+        let mut framed = [0u8; REPORT_LEN + 1];
+        framed[0] = self.report_id;
+        framed[1..].copy_from_slice(report);
+        let written = self
+            .file
+            .write(&framed)
+            .map_err(|err| ControlError::File(self.path.clone(), err))?;
+        if written != framed.len() {
+            return Err(ControlError::ShortWrite {
+                written,
+                expected: framed.len(),
+            });
+        }
+        Ok(())
+    }
+
+    fn read_report(&mut self) -> ControlResult<[u8; REPORT_LEN]> {
+        Err(ControlError::InvalidArg(
+            "reading the tower back is not supported with --hidraw".into(),
+        ))
+    }
+
+    // A hidraw node has no serial to hand; its name stands in for one.
+    fn serial(&self) -> Option<String> {
+        Some(self.path.file_name().map_or_else(
+            || "hidraw".into(),
+            |name| name.to_string_lossy().into_owned(),
+        ))
+    }
+}
+
+/// Which tower took a report, for confirming the right one was addressed.
+struct SentInfo {
+    // Bus number and device address, for a tower on USB.
+    location: Option<(u8, u8)>,
+    serial: Option<String>,
+    report: [u8; REPORT_LEN],
+}
+
+impl fmt::Display for SentInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((bus, address)) = self.location {
+            write!(f, "bus={bus:03} address={address:03} ")?;
+        }
+        write!(
+            f,
+            "serial={} report={}",
+            self.serial.as_deref().unwrap_or("-"),
+            format_report(&self.report)
+        )
+    }
+}
+
+fn open_report_file(path: &Path) -> ControlResult<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| ControlError::ReportFile(path.to_path_buf(), err))
+}
+
+fn append_report_line(
+    file: &mut File,
+    path: &Path,
+    report: &[u8; REPORT_LEN],
+) -> ControlResult<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    writeln!(
+        file,
+        "{}.{:03} {}",
+        now.as_secs(),
+        now.subsec_millis(),
+        format_report(report)
+    )
+    .map_err(|err| ControlError::ReportFile(path.to_path_buf(), err))
+}
+
+/// Commands that write `report` to the tower with generic tools. Both put
+/// report number 0 in front, as HID requires for devices without report ids.
+fn shell_commands(report: &[u8; REPORT_LEN], vendor_id: u16, product_id: u16) -> String {
+    let csv: Vec<_> = report.iter().map(|byte| byte.to_string()).collect();
+    let mut out = format!(
+        "hidapitester --vidpid {vendor_id:04X}:{product_id:04X} --open --send-output 0,{}\n",
+        csv.join(",")
+    );
+    if cfg!(target_os = "linux") {
+        let escaped: String = report.iter().map(|byte| format!("\\x{byte:02x}")).collect();
+        out.push_str(&format!(
+            "printf '\\x00{escaped}' | sudo tee /dev/hidrawX >/dev/null  # X: the hidraw node whose device/uevent names {vendor_id:04X}:{product_id:04X}\n"
+        ));
+    }
+    out
+}
+
+/// Semantic checks beyond nibble ranges: framing bytes, undefined pattern
+/// values, and field combinations the firmware would silently ignore.
+fn validate_report(report: &[u8; REPORT_LEN]) -> Vec<String> {
+    let mut problems = Vec::new();
+    if report[0] != COMMAND_VERSION || report[1] != COMMAND_ID {
+        problems.push(format!(
+            "header {:#04x} {:#04x} is not a control command",
+            report[0], report[1]
+        ));
+    }
+    if nibble(report[6]) != 0 {
+        problems.push("low nibble of the white byte is unused and must be 0".into());
+    }
+    if report[7] != 0 {
+        problems.push("trailing byte is reserved and must be 0".into());
+    }
+
+    let state = decode_report(report);
+    for (name, led) in COLOR_NAMES.iter().zip(state.leds) {
+        if !is_defined_pattern(led) {
+            problems.push(format!("{name} LED state {led:#x} is undefined"));
+        }
+    }
+    if !is_defined_pattern(state.buzzer_pattern) {
+        problems.push(format!(
+            "buzzer pattern {:#x} is undefined",
+            state.buzzer_pattern
+        ));
+    }
+    if state.buzzer_limit != 0
+        && (state.buzzer_pattern == BUZZER_OFF || state.buzzer_pattern == BUZZER_KEEP)
+    {
+        problems.push(format!(
+            "buzzer limit {} has no effect while the pattern is {}",
+            state.buzzer_limit,
+            led_state_name(state.buzzer_pattern)
+        ));
+    }
+    problems
+}
+
+/// Off, on, the four hardware patterns, and keep; LED and buzzer nibbles share
+/// this numbering.
+fn is_defined_pattern(value: u8) -> bool {
+    value <= 0x5 || value == LED_KEEP
+}
+
+/// The lamps as ANSI-colored blocks, red to white: bright when solid, shaded
+/// when patterned, dim when off and blank when kept.
+fn mirror_line(state: &TowerState) -> String {
+    const ANSI_COLORS: [u8; COLOR_COUNT] = [31, 33, 32, 34, 37];
+    state
+        .leds
+        .iter()
+        .zip(ANSI_COLORS)
+        .map(|(&led, ansi)| match led {
+            LED_KEEP => "  ".to_string(),
+            LED_OFF => format!("\x1b[2;{ansi}m\u{2591}\u{2591}\x1b[0m"),
+            LED_ON => format!("\x1b[1;{ansi}m\u{2588}\u{2588}\x1b[0m"),
+            _ => format!("\x1b[1;{ansi}m\u{2593}\u{2593}\x1b[0m"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Replaces each `{field}` in `template` with that field of `report`, lamps
+/// and buzzer by alias name. Unknown or unclosed placeholders are errors;
+/// `run` checks the template once up front so later fills cannot fail.
+fn fill_template(template: &str, report: &[u8; REPORT_LEN]) -> Result<String, String> {
+    let state = decode_report(report);
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let (field, after) = rest[start + 1..]
+            .split_once('}')
+            .ok_or_else(|| format!("unclosed placeholder in '{template}'"))?;
+        let value = match field {
+            "report" => format_report(report),
+            "buzzer" => led_state_name(state.buzzer_pattern),
+            "limit" => state.buzzer_limit.to_string(),
+            "pitch_a" => format!("{:#x}", state.pitch_a),
+            "pitch_b" => format!("{:#x}", state.pitch_b),
+            _ => match color_alias(field) {
+                Some(color) => led_state_name(state.leds[usize::from(color)]),
+                None => return Err(format!("unknown placeholder {{{field}}}")),
+            },
+        };
+        out.push_str(&value);
+        rest = after;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// One row per byte or nibble of `report`: where it sits, which field it is,
+/// its value, and the alias that value goes by.
+fn explain_bits_table(report: &[u8; REPORT_LEN]) -> String {
+    let [version, id, buzzer, pitch, led_ry, led_gb, led_w, reserved] = *report;
+    let hi = |byte: u8| byte >> 4;
+    let rows: [(usize, &str, &str, u8, String); REPORT_LEN + 5] = [
+        (0, "-", "command version", version, String::new()),
+        (1, "-", "command id", id, String::new()),
+        (2, "hi", "buzzer limit", hi(buzzer), String::new()),
+        (
+            2,
+            "lo",
+            "buzzer pattern",
+            nibble(buzzer),
+            led_state_name(nibble(buzzer)),
+        ),
+        (3, "hi", "pitch A", hi(pitch), String::new()),
+        (3, "lo", "pitch B", nibble(pitch), String::new()),
+        (4, "hi", "red", hi(led_ry), led_state_name(hi(led_ry))),
+        (
+            4,
+            "lo",
+            "yellow",
+            nibble(led_ry),
+            led_state_name(nibble(led_ry)),
+        ),
+        (5, "hi", "green", hi(led_gb), led_state_name(hi(led_gb))),
+        (
+            5,
+            "lo",
+            "blue",
+            nibble(led_gb),
+            led_state_name(nibble(led_gb)),
+        ),
+        (6, "hi", "white", hi(led_w), led_state_name(hi(led_w))),
+        (6, "lo", "unused", nibble(led_w), String::new()),
+        (7, "-", "reserved", reserved, String::new()),
+    ];
+    let mut out = format!(
+        "{:<5} {:<6} {:<16} {:<6} {}\n",
+        "byte", "nibble", "field", "value", "alias"
+    );
+    for (byte, half, field, value, alias) in &rows {
+        let value = format!("{value:#04x}");
+        let line = format!("{byte:<5} {half:<6} {field:<16} {value:<6} {alias}");
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Features that depend on the platform this binary was built for.
+const PLATFORM_FEATURES: &[(&str, bool)] = &[
+    ("socket", cfg!(unix)),
+    ("fifo", cfg!(unix)),
+    ("hidraw", cfg!(target_os = "linux")),
+    ("install-service", cfg!(target_os = "linux")),
+];
+
+fn print_info(json: bool) {
+    let products: Vec<_> = SUPPORTED_PRODUCTS
+        .iter()
+        .map(|product| format!("{VENDOR_ID:04x}:{product:04x}"))
+        .collect();
+    let features: Vec<_> = PLATFORM_FEATURES
+        .iter()
+        .filter(|&&(_, enabled)| enabled)
+        .map(|&(name, _)| name)
+        .collect();
+    let version = env!("CARGO_PKG_VERSION");
+    if json {
+        let strings = |items: &[&str]| {
+            items
+                .iter()
+                .map(|item| json_string(item))
+                .collect::<Vec<_>>()
+        };
+        let products: Vec<_> = products.iter().map(String::as_str).collect();
+        println!(
+            "{{\"version\":{},\"devices\":[{}],\"interface\":0,\"endpoint_out\":{ENDPOINT_OUT},\"endpoint_in\":{ENDPOINT_IN},\"timeout_ms\":{TIMEOUT_MS},\"remote_timeout_ms\":{REMOTE_TIMEOUT_MS},\"busy_retry_attempts\":{BUSY_RETRY_ATTEMPTS},\"busy_retry_delay_ms\":{BUSY_RETRY_DELAY_MS},\"features\":[{}]}}",
+            json_string(version),
+            strings(&products).join(","),
+            strings(&features).join(",")
+        );
+    } else {
+        println!("version             {version}");
+        println!("devices             {}", products.join(" "));
+        println!("interface           0");
+        println!("endpoints           out {ENDPOINT_OUT:#04x}, in {ENDPOINT_IN:#04x}");
+        println!("timeout             {TIMEOUT_MS} ms ({REMOTE_TIMEOUT_MS} ms with --remote)");
+        println!(
+            "busy retry          {BUSY_RETRY_ATTEMPTS} attempts, {BUSY_RETRY_DELAY_MS} ms apart"
+        );
+        println!("features            {}", features.join(" "));
+    }
+}
+
+/// Where an attached tower sits on the bus and what it calls itself. String
+/// descriptors are `None` when the device could not be opened or read.
+struct DeviceInfo {
+    bus: u8,
+    address: u8,
+    serial: Option<String>,
+    product: Option<String>,
+    manufacturer: Option<String>,
+    firmware: rusb::Version,
+}
+
+impl DeviceInfo {
+    fn to_json(&self) -> String {
+        let string = |value: &Option<String>| match value {
+            Some(value) => json_string(value),
+            None => "null".into(),
+        };
+        format!(
+            "{{\"bus\":{},\"address\":{},\"serial\":{},\"product\":{},\"manufacturer\":{},\"firmware\":{}}}",
+            self.bus,
+            self.address,
+            string(&self.serial),
+            string(&self.product),
+            string(&self.manufacturer),
+            json_string(&firmware_version(self.firmware))
+        )
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bus={:03} address={:03} serial={} firmware={}",
+            self.bus,
+            self.address,
+            self.serial.as_deref().unwrap_or("-"),
+            firmware_version(self.firmware)
+        )
+    }
+}
+
+fn list_devices<T: UsbContext>(
+    context: &T,
+    vendor: u16,
+    products: &[u16],
+) -> ControlResult<Vec<DeviceInfo>> {
+    let mut found = Vec::new();
+    for device in context.devices().map_err(usb_error("enumeration"))?.iter() {
+        let Ok(descriptor) = device.device_descriptor() else {
+            continue;
+        };
+        if !is_patlite(&descriptor, vendor, products) {
+            continue;
+        }
+
+        let (serial, product, manufacturer) = match device.open() {
+            Ok(handle) => (
+                handle.read_serial_number_string_ascii(&descriptor).ok(),
+                handle.read_product_string_ascii(&descriptor).ok(),
+                handle.read_manufacturer_string_ascii(&descriptor).ok(),
+            ),
+            Err(_) => (None, None, None),
+        };
+        found.push(DeviceInfo {
+            bus: device.bus_number(),
+            address: device.address(),
+            serial,
+            product,
+            manufacturer,
+            firmware: descriptor.device_version(),
+        });
+    }
+    Ok(found)
+}
+
+/// Quotes and escapes `value` as a JSON string.
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn light_report(colors: &[u8], state: u8) -> ControlResult<[u8; REPORT_LEN]> {
+    let (led_ry, led_gb, led_w) = assemble_leds(colors, state)?;
+    Ok(build_report(BUZZER_KEEP, 0, led_ry, led_gb, led_w))
+}
+
+/// Per-color stand-ins for solid under `--cvd`. Red and green, the pair most
+/// often confused, differ by blinking versus steady.
+const CVD_PATTERNS: [u8; COLOR_COUNT] = [0x2, 0x3, LED_ON, 0x4, 0x5];
+
+/// `CVD_PATTERNS` with any `[cvd]` overrides from the config applied, e.g.
+/// `green = "pattern4"`.
+fn cvd_patterns(config: &Config) -> ControlResult<[u8; COLOR_COUNT]> {
+    let mut patterns = CVD_PATTERNS;
+    for (color, value) in config.table("cvd").map_err(ControlError::Config)? {
+        let index = color_alias(color)
+            .ok_or_else(|| ControlError::Config(format!("[cvd]: unknown color `{color}`")))?;
+        let state = config
+            .string_in("cvd", color, value)
+            .and_then(parse_led_state)
+            .map_err(|err| ControlError::Config(format!("[cvd] {color}: {err}")))?;
+        patterns[usize::from(index)] = state;
+    }
+    Ok(patterns)
+}
+
+/// Top-level keys the config may hold; anything else is likely a typo.
+const CONFIG_KEYS: [&str; 9] = [
+    "confirm_reset",
+    "require_selection_when_ambiguous",
+    "prefer_daemon",
+    "cvd",
+    "alert_colors",
+    "buzzer_intensity",
+    "status_exit_codes",
+    "nagios",
+    "scenes",
+];
+
+/// Every problem in the config, by running each setting through the same
+/// code that uses it.
+fn check_config(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    for key in config.root.keys() {
+        if !CONFIG_KEYS.contains(&key.as_str()) {
+            problems.push(format!("unknown key `{key}`"));
+        }
+    }
+    for key in [
+        "confirm_reset",
+        "require_selection_when_ambiguous",
+        "prefer_daemon",
+    ] {
+        if let Err(err) = config.flag(key) {
+            problems.push(err);
+        }
+    }
+    let mut check = |result: ControlResult<()>| {
+        if let Err(ControlError::Config(err)) = result {
+            problems.push(err);
+        }
+    };
+    check(cvd_patterns(config).map(drop));
+    check(status_exit_code(config, &decode_report(&reset_report(false))).map(drop));
+    for color in 0..=COLOR_WHITE {
+        check(alert_color_report(config, color).map(drop));
+    }
+    check(buzzer_intensity_pattern(config, 1).map(drop));
+    for state in NagiosState::value_variants() {
+        check(nagios_report(config, *state, false).map(drop));
+    }
+    match config.table("scenes") {
+        Ok(scenes) => {
+            for (name, _) in scenes {
+                check(scene_state(config, name).map(drop));
+            }
+        }
+        Err(err) => problems.push(err),
+    }
+    match config.table("nagios") {
+        Ok(tables) => {
+            for (name, _) in tables {
+                if NagiosState::from_str(name, true).is_err() {
+                    problems.push(format!("[nagios]: unknown state `{name}`"));
+                }
+            }
+        }
+        Err(err) => problems.push(err),
+    }
+    problems.dedup();
+    problems
+}
+
+/// The lamp solid on plus the buzzer pattern `[alert_colors]` gives its
+/// color, e.g. `red = "pattern3"`, sounding until reset. Colors the config
+/// does not name leave the buzzer as it is.
+fn alert_color_report(config: &Config, color: u8) -> ControlResult<[u8; REPORT_LEN]> {
+    let mut buzzer = BUZZER_KEEP;
+    for (name, value) in config.table("alert_colors").map_err(ControlError::Config)? {
+        let index = color_alias(name).ok_or_else(|| {
+            ControlError::Config(format!("[alert_colors]: unknown color `{name}`"))
+        })?;
+        if index != color {
+            continue;
+        }
+        buzzer = config
+            .string_in("alert_colors", name, value)
+            .and_then(parse_buzzer_pattern)
+            .map_err(|err| ControlError::Config(format!("[alert_colors] {name}: {err}")))?;
+    }
+
+    let (led_ry, led_gb, led_w) = assemble_leds(&[color], LED_ON)?;
+    let pitch = if buzzer == BUZZER_KEEP {
+        0
+    } else {
+        (BUZZER_PITCH_DEFAULT_A << 4) | BUZZER_PITCH_DEFAULT_B
+    };
+    Ok(build_report(buzzer, pitch, led_ry, led_gb, led_w))
+}
+
+/// Exit status for `status --exit-code` while each lamp is lit: red 2,
+/// yellow 1, the rest 0. The highest code among lit lamps wins.
+const STATUS_EXIT_CODES: [u8; COLOR_COUNT] = [2, 1, 0, 0, 0];
+
+/// The exit status for a read-back state, with `[status_exit_codes]`
+/// overrides from the config applied, e.g. `blue = 3`. A lamp counts as lit
+/// in any state but off.
+fn status_exit_code(config: &Config, state: &TowerState) -> ControlResult<u8> {
+    let mut codes = STATUS_EXIT_CODES;
+    for (color, value) in config
+        .table("status_exit_codes")
+        .map_err(ControlError::Config)?
+    {
+        let index = color_alias(color).ok_or_else(|| {
+            ControlError::Config(format!("[status_exit_codes]: unknown color `{color}`"))
+        })?;
+        let code = config
+            .integer_in("status_exit_codes", color, value)
+            .and_then(|code| {
+                u8::try_from(code).map_err(|_| format!("{code} is not an exit status (0-255)"))
+            })
+            .map_err(|err| ControlError::Config(format!("[status_exit_codes] {color}: {err}")))?;
+        codes[usize::from(index)] = code;
+    }
+    Ok(state
+        .leds
+        .iter()
+        .zip(codes)
+        .filter(|&(&led, _)| led != LED_OFF && led != LED_KEEP)
+        .map(|(_, code)| code)
+        .max()
+        .unwrap_or(0))
+}
+
+/// The full report for a check result. A `[nagios.<state>]` table replaces the
+/// default scene outright: lamps it does not name are off, as is the buzzer
+/// unless it sets `buzzer`, e.g. `red = "pattern1"` and `buzzer = "pattern2"`.
+fn nagios_report(
+    config: &Config,
+    state: NagiosState,
+    no_buzzer: bool,
+) -> ControlResult<[u8; REPORT_LEN]> {
+    let (leds, buzzer) = state.default_scene();
+    let mut scene = scene_from_parts(leds, buzzer);
+    for (name, value) in config.table("nagios").map_err(ControlError::Config)? {
+        if name == state.name() {
+            let table = config
+                .table_in("nagios", name, value)
+                .map_err(ControlError::Config)?;
+            scene = parse_scene(config, &format!("nagios.{name}"), table)?;
+        }
+    }
+
+    if no_buzzer {
+        scene.buzzer_pattern = BUZZER_OFF;
+        (scene.pitch_a, scene.pitch_b) = (PITCH_OFF, PITCH_OFF);
+    }
+    Ok(scene.to_report())
+}
+
+/// The state `[scenes.<name>]` describes, e.g. `green = "on"` and
+/// `buzzer = "pattern1"`. An unknown name lists the scenes there are.
+fn scene_state(config: &Config, name: &str) -> ControlResult<TowerState> {
+    let mut known = Vec::new();
+    for (scene, value) in config.table("scenes").map_err(ControlError::Config)? {
+        if scene == name {
+            let table = config
+                .table_in("scenes", scene, value)
+                .map_err(ControlError::Config)?;
+            return parse_scene(config, &format!("scenes.{scene}"), table);
+        }
+        known.push(scene.as_str());
+    }
+    Err(ControlError::InvalidArg(match known.as_slice() {
+        [] => format!("unknown scene `{name}` (no [scenes] are configured)"),
+        known => format!("unknown scene `{name}` (available: {})", known.join(", ")),
+    }))
+}
+
+/// A scene table of lamp colors and `buzzer`, each mapped to the same
+/// aliases the command line takes, plus optional `limit`, `pitch_a` and
+/// `pitch_b` nibbles for the buzzer. Lamps it does not name are off, as is
+/// the buzzer unless it sets one.
+fn parse_scene(config: &Config, section: &str, table: &config::Table) -> ControlResult<TowerState> {
+    let mut leds = [LED_OFF; COLOR_COUNT];
+    let mut buzzer = BUZZER_OFF;
+    let mut nibbles = [None; 3];
+    for (key, value) in table {
+        let parsed = if let Some(slot) = ["limit", "pitch_a", "pitch_b"]
+            .iter()
+            .position(|name| name == key)
+        {
+            config
+                .integer_in(section, key, value)
+                .and_then(|value| {
+                    u8::try_from(value)
+                        .ok()
+                        .filter(|&value| value <= 0x0F)
+                        .ok_or_else(|| format!("{value} is out of range (0-15)"))
+                })
+                .map(|value| nibbles[slot] = Some(value))
+        } else {
+            let text = config
+                .string_in(section, key, value)
+                .map_err(ControlError::Config)?;
+            if key == "buzzer" {
+                parse_buzzer_pattern(text).map(|pattern| buzzer = pattern)
+            } else if let Some(color) = color_alias(key) {
+                parse_led_state(text).map(|led| leds[usize::from(color)] = led)
+            } else {
+                Err(format!("unknown key `{key}`"))
+            }
+        };
+        parsed.map_err(|err| ControlError::Config(format!("[{section}] {key}: {err}")))?;
+    }
+    let mut scene = scene_from_parts(leds, buzzer);
+    let [limit, pitch_a, pitch_b] = nibbles;
+    scene.buzzer_limit = limit.unwrap_or(scene.buzzer_limit);
+    scene.pitch_a = pitch_a.unwrap_or(scene.pitch_a);
+    scene.pitch_b = pitch_b.unwrap_or(scene.pitch_b);
+    Ok(scene)
+}
+
+/// A scene's full state: a sounding buzzer runs continuously at the default
+/// pitches.
+fn scene_from_parts(leds: [u8; COLOR_COUNT], buzzer: u8) -> TowerState {
+    let (pitch_a, pitch_b) = if buzzer == BUZZER_OFF || buzzer == BUZZER_KEEP {
+        (PITCH_OFF, PITCH_OFF)
+    } else {
+        (BUZZER_PITCH_DEFAULT_A, BUZZER_PITCH_DEFAULT_B)
+    };
+    TowerState {
+        leds,
+        buzzer_pattern: buzzer,
+        buzzer_limit: 0,
+        pitch_a,
+        pitch_b,
+    }
+}
+
+/// The report that takes the tower from `current` to `target` touching only
+/// what differs: matching lamps are kept, and so is the buzzer unless its
+/// pattern, limit or pitch changes. `None` when nothing would change.
+fn minimal_report(current: &[u8; REPORT_LEN], target: &TowerState) -> Option<[u8; REPORT_LEN]> {
+    let mut minimal = TowerState {
+        leds: [LED_KEEP; COLOR_COUNT],
+        buzzer_pattern: BUZZER_KEEP,
+        buzzer_limit: 0,
+        pitch_a: PITCH_OFF,
+        pitch_b: PITCH_OFF,
+    };
+    let mut changed = false;
+    for change in report_diff(current, &target.to_report()) {
+        match change.field {
+            Field::Led(color) if change.to != LED_KEEP => {
+                minimal.leds[usize::from(color)] = change.to;
+                changed = true;
+            }
+            Field::Led(_) => {}
+            _ if target.buzzer_pattern != BUZZER_KEEP => {
+                minimal.buzzer_pattern = target.buzzer_pattern;
+                minimal.buzzer_limit = target.buzzer_limit;
+                (minimal.pitch_a, minimal.pitch_b) = (target.pitch_a, target.pitch_b);
+                changed = true;
+            }
+            _ => {}
+        }
+    }
+    changed.then(|| minimal.to_report())
+}
+
+/// Scene names become bare config keys, so they share their character set.
+fn parse_scene_name(value: &str) -> Result<String, String> {
+    let bare = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if value.is_empty() || !value.chars().all(bare) {
+        return Err(format!(
+            "invalid scene name '{value}' (letters, digits, '_' and '-' only)"
+        ));
+    }
+    Ok(value.to_string())
+}
+
+/// Asks for every lamp and the buzzer in turn, re-asking until the answer
+/// parses, and returns the `key = value` entries of the scene table. An
+/// empty answer leaves that lamp or the buzzer off.
+fn prompt_scene(name: &str) -> ControlResult<Vec<(String, String)>> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return Err(ControlError::InvalidArg(
+            "scene-edit asks questions and needs a terminal on stdin".into(),
+        ));
+    }
+
+    let choices = [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, LED_KEEP].map(led_state_name);
+    let keys = COLOR_NAMES.iter().map(|color| (*color, false));
+    let mut entries = Vec::new();
+    eprintln!(
+        "Scene `{name}`: answer with {} (empty for off)",
+        choices.join(", ")
+    );
+    for (key, is_buzzer) in keys.chain([("buzzer", true)]) {
+        let value = loop {
+            eprint!("{key}: ");
+            let mut answer = String::new();
+            if stdin
+                .lock()
+                .read_line(&mut answer)
+                .map_err(ControlError::Io)?
+                == 0
+            {
+                return Err(ControlError::InvalidArg("scene-edit cancelled".into()));
+            }
+            let answer = match answer.trim() {
+                "" => "off",
+                answer => answer,
+            };
+            let parsed = if is_buzzer {
+                parse_buzzer_pattern(answer)
+            } else {
+                parse_led_state(answer)
+            };
+            match parsed {
+                Ok(value) => break value,
+                Err(err) => eprintln!("{err}"),
+            }
+        };
+        entries.push((key.to_string(), led_state_name(value)));
+    }
+    Ok(entries)
+}
+
+/// Writes `[scenes.<name>]` into the config file at `path`, dropping any
+/// table of that name already there and leaving every other line, comments
+/// included, as it was. The result is parsed before it replaces the file.
+fn save_scene(path: &Path, name: &str, entries: &[(String, String)]) -> ControlResult<()> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(ControlError::File(path.into(), err)),
+    };
+
+    let target = format!("scenes.{name}");
+    let mut kept = String::new();
+    let mut skipping = false;
+    for line in text.lines() {
+        if let Some(header) = line.trim().strip_prefix('[')
+            && let Some((header, _)) = header.split_once(']')
+        {
+            let header: Vec<_> = header.split('.').map(str::trim).collect();
+            skipping = header.join(".") == target;
+        }
+        if !skipping {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+
+    if !kept.is_empty() && !kept.ends_with("\n\n") {
+        kept.push('\n');
+    }
+    kept.push_str(&format!("[{target}]\n"));
+    for (key, value) in entries {
+        kept.push_str(&format!("{key} = \"{value}\"\n"));
+    }
+    config::parse(&kept)
+        .map_err(|err| ControlError::Config(format!("{}: {err}", path.display())))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| ControlError::File(dir.into(), err))?;
+    }
+    fs::write(path, kept).map_err(|err| ControlError::File(path.into(), err))
+}
+
+/// Legacy alert-code digits and the lamp states they stand for. Blink maps
+/// to the first hardware pattern; the buzzer digit uses the same table.
+const COMPAT_DIGITS: [(char, u8); 4] = [('0', LED_OFF), ('1', LED_ON), ('2', 0x2), ('9', LED_KEEP)];
+
+/// Translates a legacy alert code: one digit per lamp from red to white,
+/// optionally followed by a buzzer digit (kept when absent).
+fn compat_report(code: &str) -> Result<[u8; REPORT_LEN], String> {
+    let states = code
+        .chars()
+        .map(|digit| {
+            COMPAT_DIGITS
+                .iter()
+                .find(|(known, _)| *known == digit)
+                .map(|&(_, state)| state)
+                .ok_or_else(|| format!("unknown alert digit '{digit}' (expected 0, 1, 2 or 9)"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (leds, buzzer) = match states.as_slice() {
+        [leds @ .., buzzer] if leds.len() == COLOR_COUNT => (leds, *buzzer),
+        leds if leds.len() == COLOR_COUNT => (leds, BUZZER_KEEP),
+        _ => return Err(format!("alert code '{code}' must have 5 or 6 digits")),
+    };
+    let [red, yellow, green, blue, white] = leds.try_into().expect("length checked above");
+    let pitch = if buzzer == BUZZER_OFF || buzzer == BUZZER_KEEP {
+        PITCH_OFF
+    } else {
+        (BUZZER_PITCH_DEFAULT_A << 4) | BUZZER_PITCH_DEFAULT_B
+    };
+    Ok(build_report(
+        buzzer,
+        pitch,
+        (red << 4) | yellow,
+        (green << 4) | blue,
+        white << 4,
+    ))
+}
+
+/// Guard for shared towers: asks on a terminal, and refuses outright when
+/// nobody is there to answer.
+fn confirm_reset() -> ControlResult<()> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return Err(ControlError::InvalidArg(
+            "reset turns off the whole tower and confirm_reset is set; pass --yes to confirm"
+                .into(),
+        ));
+    }
+
+    eprint!("Turn off every lamp on the tower? [y/N] ");
+    let mut answer = String::new();
+    stdin
+        .lock()
+        .read_line(&mut answer)
+        .map_err(ControlError::Io)?;
+    match answer.trim().to_ascii_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err(ControlError::InvalidArg("reset cancelled".into())),
+    }
+}
+
+/// All LEDs off; the buzzer is either silenced or left untouched with
+/// `BUZZER_KEEP`, which the firmware treats as "no change" even mid-pattern.
+fn reset_report(keep_buzzer: bool) -> [u8; REPORT_LEN] {
+    let buzzer = if keep_buzzer { BUZZER_KEEP } else { BUZZER_OFF };
+    build_report(buzzer, PITCH_OFF, LED_OFF, LED_OFF, LED_OFF)
+}
+
+fn parse_color(value: &str) -> Result<u8, String> {
+    if let Some(alias) = color_alias(value) {
+        return Ok(alias);
+    }
+
+    let num = parse_u8_any(value).map_err(|_| format!("unknown color '{value}'"))?;
+    check_color_index(num)
+}
+
+fn parse_color_index(value: &str) -> Result<u8, String> {
+    let num = parse_u8_any(value).map_err(|_| format!("invalid color index '{value}'"))?;
+    check_color_index(num)
+}
+
+fn check_color_index(num: u8) -> Result<u8, String> {
+    if num <= COLOR_WHITE {
+        Ok(num)
+    } else {
+        Err(format!(
+            "color index {num} out of range (0-{})",
+            COLOR_WHITE
+        ))
+    }
+}
+
+/// Roughly what each lamp looks like lit, for matching arbitrary colors.
+const LAMP_RGB: [(u8, u8, u8); COLOR_COUNT] = [
+    (255, 0, 0),
+    (255, 255, 0),
+    (0, 255, 0),
+    (0, 0, 255),
+    (255, 255, 255),
+];
+
+/// CSS named colors accepted by `light --name`, a common subset.
+const CSS_COLORS: [(&str, (u8, u8, u8)); 27] = [
+    ("amber", (255, 191, 0)),
+    ("aqua", (0, 255, 255)),
+    ("blue", (0, 0, 255)),
+    ("crimson", (220, 20, 60)),
+    ("cyan", (0, 255, 255)),
+    ("fuchsia", (255, 0, 255)),
+    ("gold", (255, 215, 0)),
+    ("gray", (128, 128, 128)),
+    ("green", (0, 128, 0)),
+    ("grey", (128, 128, 128)),
+    ("indigo", (75, 0, 130)),
+    ("lime", (0, 255, 0)),
+    ("magenta", (255, 0, 255)),
+    ("maroon", (128, 0, 0)),
+    ("navy", (0, 0, 128)),
+    ("olive", (128, 128, 0)),
+    ("orange", (255, 165, 0)),
+    ("pink", (255, 192, 203)),
+    ("purple", (128, 0, 128)),
+    ("red", (255, 0, 0)),
+    ("silver", (192, 192, 192)),
+    ("skyblue", (135, 206, 235)),
+    ("teal", (0, 128, 128)),
+    ("tomato", (255, 99, 71)),
+    ("turquoise", (64, 224, 208)),
+    ("white", (255, 255, 255)),
+    ("yellow", (255, 255, 0)),
+];
+
+fn parse_css_color(value: &str) -> Result<u8, String> {
+    let rgb = CSS_COLORS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(value))
+        .map(|&(_, rgb)| rgb)
+        .ok_or_else(|| {
+            let names: Vec<_> = CSS_COLORS.iter().map(|(name, _)| *name).collect();
+            format!(
+                "unknown color name '{value}'; the tower only has five lamps ({}), \
+                 matched from: {}",
+                COLOR_NAMES.join(" "),
+                names.join(" ")
+            )
+        })?;
+    Ok(nearest_color(rgb))
+}
+
+/// The lamp whose lit color is closest to `rgb`; ties go to the lower id.
+fn nearest_color((r, g, b): (u8, u8, u8)) -> u8 {
+    let distance = |&(lr, lg, lb): &(u8, u8, u8)| {
+        let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).pow(2);
+        d(r, lr) + d(g, lg) + d(b, lb)
+    };
+    (0..COLOR_WHITE + 1)
+        .min_by_key(|&color| distance(&LAMP_RGB[usize::from(color)]))
+        .unwrap_or(COLOR_RED)
+}
+
+const COLOR_ALIASES: AliasTable = &[
+    ("red", COLOR_RED),
+    ("yellow", COLOR_YELLOW),
+    ("green", COLOR_GREEN),
+    ("blue", COLOR_BLUE),
+    ("white", COLOR_WHITE),
+];
+
+/// Every alias table under the name `aliases` reports it by.
+const ALIAS_TABLES: [(&str, AliasTable); 3] = [
+    ("color", COLOR_ALIASES),
+    ("led", LED_STATE_ALIASES),
+    ("buzzer", BUZZER_ALIASES),
+];
+
+/// Extra color names from `--color-alias-file`.
+static SITE_COLOR_ALIASES: OnceLock<Vec<(String, u8)>> = OnceLock::new();
+
+fn color_alias(value: &str) -> Option<u8> {
+    lookup_alias(COLOR_ALIASES, value).or_else(|| {
+        SITE_COLOR_ALIASES
+            .get()?
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(value))
+            .map(|&(_, color)| color)
+    })
+}
+
+/// The `--color-alias-file` path, picked out of the raw arguments ahead of
+/// clap.
+fn color_alias_file_arg() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--color-alias-file" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str()?.strip_prefix("--color-alias-file=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// Reads `name = "color"` lines; every target must be one of the five lamp
+/// colors (by name or id), not another site alias.
+fn load_color_aliases(path: &Path) -> ControlResult<Vec<(String, u8)>> {
+    let text = fs::read_to_string(path).map_err(|err| ControlError::File(path.into(), err))?;
+    let table = config::parse(&text)
+        .map_err(|err| ControlError::Config(format!("{}: {err}", path.display())))?;
+    table
+        .into_iter()
+        .map(|(name, value)| {
+            let color = match &value {
+                config::Value::String(target) => {
+                    lookup_alias(COLOR_ALIASES, target).or_else(|| parse_color_index(target).ok())
+                }
+                _ => None,
+            };
+            color.map(|color| (name.clone(), color)).ok_or_else(|| {
+                ControlError::Config(format!(
+                    "{}: `{name}` must name one of the five colors ({}) as a string",
+                    path.display(),
+                    COLOR_NAMES.join(" ")
+                ))
+            })
+        })
+        .collect()
+}
+
+fn parse_byte(value: &str) -> Result<u8, String> {
+    parse_u8_any(value).map_err(|_| format!("invalid byte '{value}'"))
+}
+
+/// A number with a unit suffix: `ms`, `s`, `m` or `h` (`1.5s` is fine).
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let scale = match unit {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "" => return Err(format!("duration '{value}' needs a unit (ms, s, m or h)")),
+        _ => return Err(format!("unknown duration unit in '{value}'")),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{value}'"))?;
+    Duration::try_from_secs_f64(number * scale).map_err(|_| format!("invalid duration '{value}'"))
+}
+
+/// A melody note, `PITCH:MILLIS` with a nibble pitch and a positive duration.
+fn parse_note(value: &str) -> Result<Note, String> {
+    let (pitch, millis) = value
+        .split_once(':')
+        .ok_or_else(|| format!("note '{value}' must be PITCH:MILLIS"))?;
+    let pitch = parse_nibble(pitch.trim())?;
+    let millis: u64 = millis
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid note duration '{millis}'"))?;
+    if millis == 0 {
+        return Err(format!("note '{value}' needs a positive duration"));
+    }
+    Ok(Note {
+        pitch,
+        duration: Duration::from_millis(millis),
+    })
+}
+
+fn parse_report_index(value: &str) -> Result<usize, String> {
+    let index = parse_u8_any(value).map_err(|_| format!("invalid byte index '{value}'"))?;
+    if usize::from(index) < REPORT_LEN {
+        Ok(usize::from(index))
+    } else {
+        Err(format!(
+            "byte index {index} out of range (0-{})",
+            REPORT_LEN - 1
+        ))
+    }
+}
+
+/// A `bcdDevice` version as `1.20` (the form `lsusb` prints) or raw BCD
+/// such as `0x0120`.
+fn parse_firmware_version(value: &str) -> Result<rusb::Version, String> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        return parse_u16_any(value).map(rusb::Version::from_bcd);
+    }
+    let invalid = || format!("invalid firmware version '{value}' (expected e.g. 1.20 or 0x0120)");
+    let (major, rest) = value.split_once('.').unwrap_or((value, ""));
+    let major = major.parse::<u8>().map_err(|_| invalid())?;
+    let mut digits = rest.chars().map(|c| c.to_digit(10).map(|d| d as u8));
+    let minor = digits.next().unwrap_or(Some(0)).ok_or_else(invalid)?;
+    let sub_minor = digits.next().unwrap_or(Some(0)).ok_or_else(invalid)?;
+    if digits.next().is_some() || major > 99 {
+        return Err(invalid());
+    }
+    Ok(rusb::Version(major, minor, sub_minor))
+}
+
+fn parse_u16_any(value: &str) -> Result<u16, String> {
+    if let Some(hex) = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        u16::from_str_radix(hex, 16).map_err(|_| format!("invalid hex value '{value}'"))
+    } else {
+        value
+            .parse::<u16>()
+            .map_err(|_| format!("invalid number '{value}'"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for the tower, keeping every report written to it. It
+    /// cannot be read back unless `read_times_out` makes it a tower that
+    /// has stopped answering.
+    #[derive(Default)]
+    struct MockTransport {
+        sent: Vec<[u8; REPORT_LEN]>,
+        read_times_out: bool,
+    }
+
+    impl Transport for MockTransport {
+        fn send(&mut self, report: &[u8; REPORT_LEN]) -> ControlResult<()> {
+            self.sent.push(*report);
+            Ok(())
+        }
+
+        fn read_report(&mut self) -> ControlResult<[u8; REPORT_LEN]> {
+            if self.read_times_out {
+                return Err(ControlError::ReadTimeout(Duration::from_millis(TIMEOUT_MS)));
+            }
+            Err(ControlError::InvalidArg("no read-back".into()))
+        }
+    }
+
+    /// The options of a plain invocation with no flags given.
+    fn plain_options() -> SendOptions {
+        SendOptions {
+            report_file: None,
+            report_template: None,
+            assume_device: false,
+            dry_run: false,
+            simulate: None,
+            timing: false,
+            accept_short: false,
+            hidraw: None,
+            report_id: 0,
+            retry_for: None,
+            wait_for_device: false,
+            swap_nibbles: false,
+            merge_cached: false,
+            mirror: false,
+            explain: false,
+            emit_code: None,
+            emit_shell: false,
+            cvd: false,
+            usb: UsbOptions::default(),
+            quiet: true,
+            format: OutputFormat::Text,
+            verbose: 0,
+        }
+    }
+
+    /// Runs one command line the way `run` does and returns what reached
+    /// the transport.
+    fn sent_by(line: &str) -> Vec<[u8; REPORT_LEN]> {
+        let options = plain_options();
+        let config = Config::default();
+        let mut transport = MockTransport::default();
+        let command = parse_line(line).unwrap();
+        execute(
+            command,
+            &mut Session::new(&options, &config, &mut transport),
+        )
+        .unwrap();
+        transport.sent
+    }
+
+    #[test]
+    fn tower_sends_every_lamp_in_one_report() {
+        assert_eq!(
+            sent_by("tower on off pattern1 off on"),
+            [[0x00, 0x00, 0x0F, 0x00, 0x10, 0x20, 0x10, 0x00]]
+        );
+    }
+
+    #[test]
+    fn light_keeps_the_other_lamps() {
+        assert_eq!(
+            sent_by("light red,green pattern2"),
+            [[0x00, 0x00, 0x0F, 0x00, 0x3F, 0x3F, 0xF0, 0x00]]
+        );
+    }
+
+    #[test]
+    fn buzzer_sends_pattern_limit_and_pitch() {
+        assert_eq!(
+            sent_by("buzzer buzz_on 3 14 15"),
+            [[0x00, 0x00, 0x31, 0xEF, 0xFF, 0xFF, 0xF0, 0x00]]
+        );
+    }
+
+    #[test]
+    fn all_sets_white_by_default() {
+        let every_lamp = [[0x00, 0x00, 0x0F, 0x00, 0x11, 0x11, 0x10, 0x00]];
+        assert_eq!(sent_by("all on"), every_lamp);
+        assert_eq!(sent_by("all on --include-white"), every_lamp);
+    }
+
+    #[test]
+    fn all_can_leave_white_alone() {
+        assert_eq!(
+            sent_by("all on --exclude-white"),
+            [[0x00, 0x00, 0x0F, 0x00, 0x11, 0x11, 0xF0, 0x00]]
+        );
+    }
+
+    #[test]
+    fn every_backend_reports_the_bytes_sent() {
+        let mut options = plain_options();
+        options.format = OutputFormat::Json;
+        let config = Config::default();
+        let expected = Some("{\"status\":\"ok\",\"report\":[0,0,15,0,31,255,240,0]}".into());
+
+        let mut transport = MockTransport::default();
+        let mut session = Session::new(&options, &config, &mut transport);
+        let outcome = execute(parse_line("light red on").unwrap(), &mut session).unwrap();
+        assert_eq!(outcome_line(&outcome, &options), expected);
+
+        options.simulate = Some(Simulate::Ok);
+        let mut transport = MockTransport::default();
+        let mut session = Session::new(&options, &config, &mut transport);
+        let outcome = execute(parse_line("light red on").unwrap(), &mut session).unwrap();
+        assert_eq!(outcome_line(&outcome, &options), expected);
+        assert!(transport.sent.is_empty());
+    }
+
+    #[test]
+    fn cooldown_is_skipped_without_a_tower() {
+        let mut options = plain_options();
+        options.simulate = Some(Simulate::Ok);
+        let config = Config::default();
+        let mut transport = MockTransport::default();
+        let mut session = Session::new(&options, &config, &mut transport);
+        let command = parse_line("buzzer 1 1 --cooldown-ms 1000").unwrap();
+        assert!(execute(command, &mut session).is_ok());
+    }
+
+    #[test]
+    fn no_tower_names_the_flag_given() {
+        let mut options = plain_options();
+        options.assume_device = true;
+        options.dry_run = true;
+        let err = options.no_tower().unwrap().to_string();
+        assert!(err.contains("--dry-run"), "{err}");
+    }
+
+    #[test]
+    fn probe_is_skipped_without_read_back() {
+        assert_eq!(
+            sent_by("report --probe-safe 0 0 0x77 0 0 0 0 0"),
+            [[0x00, 0x00, 0x77, 0x00, 0x00, 0x00, 0x00, 0x00]]
+        );
+    }
+
+    #[test]
+    fn probe_resets_a_tower_that_stops_answering() {
+        let options = plain_options();
+        let config = Config::default();
+        let mut transport = MockTransport {
+            read_times_out: true,
+            ..MockTransport::default()
+        };
+        let mut session = Session::new(&options, &config, &mut transport);
+        let command = parse_line("report --probe-safe 0 0 0x77 0 0 0 0 0").unwrap();
+        let result = execute(command, &mut session);
+        assert!(matches!(
+            result,
+            Err(ControlError::ProbeUnsafe { reset: None, .. })
+        ));
+        assert_eq!(
+            transport.sent,
+            [
+                [0x00, 0x00, 0x77, 0x00, 0x00, 0x00, 0x00, 0x00],
+                [0x00; REPORT_LEN],
+            ]
+        );
+    }
+
+    #[test]
+    fn scene_shows_cvd_patterns() {
+        let mut options = plain_options();
+        options.cvd = true;
+        let config = Config {
+            root: config::parse("[scenes.alert]\nred = \"on\"\ngreen = \"on\"\n").unwrap(),
+            ..Config::default()
+        };
+        let mut transport = MockTransport::default();
+        let mut session = Session::new(&options, &config, &mut transport);
+        execute(parse_line("scene alert").unwrap(), &mut session).unwrap();
+        assert_eq!(
+            transport.sent,
+            [[0x00, 0x00, 0x00, 0x00, 0x20, 0x10, 0x00, 0x00]]
+        );
+    }
+
+    #[test]
+    fn error_json_has_status_message_and_hint() {
+        assert_eq!(
+            error_json(&ControlError::InvalidArg("bad \"x\"".into())),
+            r#"{"status":"error","message":"bad \"x\"","hint":null}"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn served_lines_refuse_commands_that_run_until_stopped() {
+        let options = plain_options();
+        let config = Config::default();
+        let mut transport = MockTransport::default();
+        let mut session = Session::new(&options, &config, &mut transport);
+        for line in [
+            "monitor",
+            "heartbeat",
+            "gradient --from red --to blue --loop",
+        ] {
+            assert!(
+                matches!(
+                    serve_line(line, &mut session),
+                    Err(ControlError::InvalidArg(_))
+                ),
+                "{line}"
+            );
+        }
+        assert!(serve_line("gradient --from red --to blue --duration 1ms", &mut session).is_ok());
+    }
+
+    #[test]
+    fn daemon_lines_drop_leading_global_flags() {
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<_>>();
+        assert_eq!(
+            daemon::command_line(&args("--serial A1 --via-daemon light red on")),
+            Some("light red on".into())
+        );
+        assert_eq!(daemon::command_line(&args("light red on --quiet")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_statuses_survive_the_reply_line() {
+        let options = plain_options();
+        let reply = socket::reply_line(Ok(Outcome::Output("red on".into(), 2)), &options);
+        assert!(matches!(
+            daemon::reply_outcome(1, &reply),
+            Ok(Outcome::Output(text, 2)) if text == "red on"
+        ));
+        let reply = socket::reply_line(Ok(Outcome::Exit(1)), &options);
+        assert!(matches!(
+            daemon::reply_outcome(1, &reply),
+            Ok(Outcome::Exit(1))
+        ));
+        let reply = socket::reply_line(Err(ControlError::Interrupted), &options);
+        assert!(matches!(
+            daemon::reply_outcome(1, &reply),
+            Err(ControlError::Daemon(1, _))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn holder_notes_are_kept_by_their_writer() {
+        // No real tower sits at bus 255, address 255.
+        let tower = (255, 255);
+        let lock = holder::Lock::acquire(tower, "socket", None).unwrap();
+        assert!(holder::Lock::acquire(tower, "blink", None).is_none());
+        let current = holder::current(tower).unwrap();
+        assert_eq!(
+            (current.pid, current.command.as_str()),
+            (std::process::id(), "socket")
+        );
+        drop(lock);
+        assert!(holder::current(tower).is_none());
+    }
+
+    #[test]
+    fn read_output_reaches_the_reply_line() {
+        let options = plain_options();
+        let config = Config::default();
+        let mut transport = MockTransport::default();
+        let mut session = Session::new(&options, &config, &mut transport);
+        let outcome = execute(
+            parse_line("decode 0 0 0 0 0x10 0 0 0").unwrap(),
+            &mut session,
+        );
+        let line = outcome_line(&outcome.unwrap(), &options).unwrap();
+        assert!(line.contains("red"), "{line}");
+    }
+
+    /// The reports and holds of `frames`, holds in whole milliseconds.
+    fn frame_list(frames: &[Frame]) -> Vec<([u8; REPORT_LEN], u128)> {
+        frames
+            .iter()
+            .map(|frame| (frame.report, frame.hold.as_millis()))
+            .collect()
+    }
+
+    /// The pulse reports for red, dim to solid, with the given holds.
+    fn red_pulse(holds: [u128; 6]) -> Vec<([u8; REPORT_LEN], u128)> {
+        [0x0F, 0x2F, 0x3F, 0x4F, 0x5F, 0x1F]
+            .into_iter()
+            .zip(holds)
+            .map(|(led_ry, hold)| ([0x00, 0x00, 0x0F, 0x00, led_ry, 0xFF, 0xF0, 0x00], hold))
+            .collect()
+    }
+
+    #[test]
+    fn pulse_frames_follow_each_ease() {
+        let second = Duration::from_secs(1);
+        for (ease, holds) in [
+            (Easing::Linear, [200, 200, 200, 200, 200, 0]),
+            (Easing::EaseIn, [447, 185, 142, 119, 105, 0]),
+            (Easing::EaseOut, [105, 119, 142, 185, 447, 0]),
+        ] {
+            let frames = pulse_frames(COLOR_RED, ease, second).unwrap();
+            assert_eq!(frame_list(&frames), red_pulse(holds));
+        }
+    }
+
+    #[test]
+    fn ease_schedule_spends_the_whole_duration() {
+        let total = Duration::from_secs(3);
+        for ease in [Easing::Linear, Easing::EaseIn, Easing::EaseOut] {
+            let spent: Duration = ease_schedule(ease, total, 5).into_iter().sum();
+            assert!(total.abs_diff(spent) < Duration::from_micros(1));
+        }
+    }
+
+    #[test]
+    fn gradient_lights_one_lamp_at_a_time() {
+        let options = plain_options();
+        let config = Config::default();
+        let mut transport = MockTransport::default();
+        let session = Session::new(&options, &config, &mut transport);
+        let frames = gradient_frames(&session, COLOR_GREEN, COLOR_RED, Duration::from_millis(900));
+        assert_eq!(
+            frame_list(&frames.unwrap()),
+            [
+                ([0x00, 0x00, 0x0F, 0x00, 0x00, 0x10, 0x00, 0x00], 300),
+                ([0x00, 0x00, 0x0F, 0x00, 0x01, 0x00, 0x00, 0x00], 300),
+                ([0x00, 0x00, 0x0F, 0x00, 0x10, 0x00, 0x00, 0x00], 300),
+            ]
+        );
+    }
+
+    #[test]
+    fn play_sends_every_frame_and_waits_its_hold() {
+        let options = plain_options();
+        let config = Config::default();
+        let frames = pulse_frames(COLOR_RED, Easing::Linear, Duration::from_secs(1)).unwrap();
+        let mut transport = MockTransport::default();
+        let mut session = Session::new(&options, &config, &mut transport);
+        let mut waited = Vec::new();
+        play(&mut session, &frames, |hold| waited.push(hold.as_millis())).unwrap();
+        assert_eq!(waited, [200, 200, 200, 200, 200, 0]);
+        let reports: Vec<_> = frames.iter().map(|frame| frame.report).collect();
+        assert_eq!(transport.sent, reports);
+    }
+
+    #[test]
+    fn preview_draws_a_row_per_lamp() {
+        let options = plain_options();
+        let config = Config::default();
+        let mut transport = MockTransport::default();
+        let session = Session::new(&options, &config, &mut transport);
+        let frames = gradient_frames(
+            &session,
+            COLOR_RED,
+            COLOR_YELLOW,
+            Duration::from_millis(1200),
+        )
+        .unwrap();
+        let red = format!("{}{}", "#".repeat(30), "_".repeat(30));
+        let yellow = format!("{}{}", "_".repeat(30), "#".repeat(30));
+        let off = "_".repeat(60);
+        assert_eq!(
+            preview_timeline(&frames),
+            format!(
+                "red    {red}\nyellow {yellow}\ngreen  {off}\nblue   {off}\nwhite  {off}\n       \
+                 1 column = 20ms, 1.20s in all\n"
+            )
+        );
+    }
+
+    #[test]
+    fn reset_sends_the_all_off_report() {
+        assert_eq!(sent_by("reset"), [[0x00; REPORT_LEN]]);
+    }
+
+    #[test]
+    fn reset_report_turns_everything_off() {
+        assert_eq!(
+            reset_report(false),
+            [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn reset_report_can_leave_the_buzzer_alone() {
+        assert_eq!(
+            reset_report(true),
+            [0x00, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
     }
 }
@@ -0,0 +1,52 @@
+//! Runs every case in `tests/golden/reports.txt` through the binary with
+//! `--assume-device` and compares the assembled report byte for byte.
+
+use std::process::Command;
+
+const CASES: &str = include_str!("golden/reports.txt");
+
+#[test]
+fn reports_match_golden_table() {
+    let mut failures = Vec::new();
+
+    for (index, line) in CASES.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (args, expected) = line
+            .split_once("=>")
+            .unwrap_or_else(|| panic!("line {}: expected `args => bytes`", index + 1));
+        let expected: Vec<String> = expected
+            .split_whitespace()
+            .map(|byte| format!("0x{byte}"))
+            .collect();
+
+        let output = Command::new(env!("CARGO_BIN_EXE_ptltectl"))
+            .arg("--assume-device")
+            .args(args.split_whitespace())
+            .env("XDG_CONFIG_HOME", "/nonexistent")
+            .env_remove("PTLTECTL_CONFIG_PATH")
+            .output()
+            .expect("failed to run ptltectl");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let actual: Vec<String> = stdout
+            .split_whitespace()
+            .take(expected.len())
+            .map(str::to_string)
+            .collect();
+
+        if !output.status.success() || actual != expected {
+            failures.push(format!(
+                "line {}: `{}`\n  expected {}\n  got      {}{}",
+                index + 1,
+                args.trim(),
+                expected.join(" "),
+                stdout.trim(),
+                String::from_utf8_lossy(&output.stderr).trim(),
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
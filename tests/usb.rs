@@ -0,0 +1,23 @@
+//! Hands `UsbTransport::with_handle` a handle opened outside the library,
+//! as a host with its own USB context would. Needs a tower attached, so it
+//! only runs with `cargo test -- --ignored`.
+
+use ptltectl::{
+    BUZZER_KEEP, LED_KEEP_HIGH, LED_KEEP_PAIR, PRODUCT_ID, Transport, UsbTransport, VENDOR_ID,
+    build_report,
+};
+use std::time::Duration;
+
+#[test]
+#[ignore = "needs a tower attached"]
+fn with_handle_claims_and_writes() {
+    let handle = rusb::open_device_with_vid_pid(VENDOR_ID, PRODUCT_ID).expect("no tower attached");
+    let (bus, address) = (handle.device().bus_number(), handle.device().address());
+    let mut tower = UsbTransport::with_handle(handle, Duration::from_millis(500)).unwrap();
+    assert_eq!(tower.location(), Some((bus, address)));
+    assert_eq!(tower.setup_timing().open, None);
+
+    // Every field kept, so the tower does not visibly change.
+    let keep = build_report(BUZZER_KEEP, 0, LED_KEEP_PAIR, LED_KEEP_PAIR, LED_KEEP_HIGH);
+    tower.send(&keep).unwrap();
+}